@@ -1,4 +1,4 @@
-use crate::{ interval::Interval, vector3::Point3 };
+use crate::{ interval::Interval, vector3::Point3, ray::Ray, stats };
 
 #[derive(Copy, Clone, Debug)]
 pub struct Aabb {
@@ -7,10 +7,13 @@ pub struct Aabb {
     pub z: Interval,
 }
 
-impl Aabb {
-    pub fn default() -> Self {
+impl Default for Aabb {
+    fn default() -> Self {
         Aabb { x: Interval::empty(), y: Interval::empty(), z: Interval::empty() }
     }
+}
+
+impl Aabb {
     pub fn new(ix: Interval, iy: Interval, iz: Interval) -> Self {
         Aabb { x: ix, y: iy, z: iz }
     }
@@ -40,18 +43,46 @@ impl Aabb {
         let new_y = if self.y.size() >= delta { self.y } else { self.y.expand(delta) };
         let new_z = if self.z.size() >= delta { self.z } else { self.z.expand(delta) };
 
-        return Aabb::new(new_x, new_y, new_z);
+        Aabb::new(new_x, new_y, new_z)
     }
 
-    // pub fn axis(&self, n: i32) -> Interval {
-    //     if n == 1 {
-    //         return self.y;
-    //     }
-    //     if n == 2 {
-    //         return self.z;
-    //     }
-    //     return self.x;
-    // }
+    pub fn axis(&self, n: i32) -> Interval {
+        if n == 1 {
+            return self.y;
+        }
+        if n == 2 {
+            return self.z;
+        }
+        self.x
+    }
+
+    pub fn clip(&self, r: &Ray, mut ray_t: Interval) -> Option<Interval> {
+        // Return the part of ray_t for which the ray lies inside the box, if any.
+        stats::count_bbox_test();
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction().index(a);
+            let orig = r.origin().index(a);
+
+            let mut t0 = (self.axis(a as i32).min - orig) * inv_d;
+            let mut t1 = (self.axis(a as i32).max - orig) * inv_d;
+
+            if inv_d < 0.0 {
+                (t0, t1) = (t1, t0);
+            }
+
+            if t0 > ray_t.min {
+                ray_t.min = t0;
+            }
+            if t1 < ray_t.max {
+                ray_t.max = t1;
+            }
+
+            if ray_t.max <= ray_t.min {
+                return None;
+            }
+        }
+        Some(ray_t)
+    }
 
     // pub fn hit(&self, r: &Ray, mut ray_t: Interval) -> bool {
     //     for a in 0..3 {
@@ -0,0 +1,462 @@
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use crate::{
+    camera::Camera,
+    helper::get_nested_yaml_value,
+    rtweekend::degrees_to_radians,
+    vector3::{ Vector3, cross, dot, unit_vector },
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum Interpolation {
+    Linear, // Straight lines between keys
+    Bezier, // Cubic curves between keys, shaped by each key's handles
+    CatmullRom, // A smooth curve through every key, shaped by the keys either side
+}
+
+impl Interpolation {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "linear" => Ok(Interpolation::Linear),
+            "bezier" => Ok(Interpolation::Bezier),
+            "catmullrom" => Ok(Interpolation::CatmullRom),
+            _ => Err(format!("unknown interpolation '{}'", name)),
+        }
+    }
+}
+
+// The value of a setting at one frame. Bezier handles are offsets from the value, and default
+// to zero, which eases in and out of the key.
+struct Key {
+    frame: f64,
+    value: Vec<f64>,
+    handle_in: Vec<f64>,
+    handle_out: Vec<f64>,
+}
+
+// The keys of one scene setting, such as camera.lookfrom or materials.ground.colour.
+struct Track {
+    target: String,
+    interpolation: Interpolation,
+    scalar: bool, // Whether the value is a single number rather than a list
+    keys: Vec<Key>,
+}
+
+// Camera positions spread evenly around a circle about lookat, keeping the distance and height
+// of lookfrom.
+struct Turntable {
+    degrees: f64, // How far round the camera goes over the frames
+    axis: Option<Vector3>, // Axis to turn about, or None for the camera's vup
+}
+
+pub struct Animation {
+    pub first_frame: i32,
+    pub last_frame: i32,
+    pub fps: f64,
+    tracks: Vec<Track>,
+    turntable: Option<Turntable>,
+}
+
+impl Animation {
+    pub fn from_scene(scene: &Value) -> Result<Option<Animation>, String> {
+        // Reads the scene's animation section and the camera's turntable, if it has them.
+        let turntable_value = scene.get("camera").and_then(|camera| camera.get("turntable"));
+        let animation_value = scene.get("animation");
+
+        let mut turntable_frames = 0;
+        let turntable = match turntable_value {
+            Some(Value::Number(frames)) => {
+                turntable_frames = frames.as_i64().unwrap_or(0) as i32;
+                Some(Turntable { degrees: 360.0, axis: None })
+            }
+            Some(value) => {
+                turntable_frames = get_nested_yaml_value(value, "frames").unwrap_or(0);
+                let degrees = get_nested_yaml_value(value, "degrees").unwrap_or(360.0);
+                let axis = get_nested_yaml_value::<[f64; 3]>(value, "axis").map(|axis| {
+                    Vector3::new(axis[0], axis[1], axis[2])
+                });
+                if axis.is_some_and(|axis| axis.near_zero()) {
+                    return Err("turntable axis must not be zero".to_string());
+                }
+                Some(Turntable { degrees, axis })
+            }
+            None => None,
+        };
+
+        let Some(animation_value) = animation_value else {
+            // A turntable on its own renders its frames from 1.
+            return match turntable {
+                Some(_) if turntable_frames < 1 => {
+                    Err("turntable needs at least one frame".to_string())
+                }
+                Some(_) => {
+                    Ok(
+                        Some(Animation {
+                            first_frame: 1,
+                            last_frame: turntable_frames,
+                            fps: 24.0,
+                            tracks: Vec::new(),
+                            turntable,
+                        })
+                    )
+                }
+                None => Ok(None),
+            };
+        };
+
+        let [first_frame, last_frame] = get_nested_yaml_value(animation_value, "frames").unwrap_or([
+            1, 1,
+        ]);
+        if last_frame < first_frame {
+            return Err("animation frames must be [first, last]".to_string());
+        }
+        let fps = get_nested_yaml_value(animation_value, "fps").unwrap_or(24.0);
+
+        let mut tracks = Vec::new();
+        if let Some(Value::Sequence(track_values)) = animation_value.get("tracks") {
+            for track_value in track_values {
+                let target: String = get_nested_yaml_value(track_value, "target").ok_or(
+                    "animation track has no target"
+                )?;
+                let interpolation = match
+                    get_nested_yaml_value::<String>(track_value, "interpolation")
+                {
+                    Some(name) => Interpolation::from_name(&name)?,
+                    None => Interpolation::Linear,
+                };
+
+                let mut keys = Vec::new();
+                let mut scalar = false;
+                if let Some(Value::Sequence(key_values)) = track_value.get("keys") {
+                    for key_value in key_values {
+                        let frame = match get_nested_yaml_value::<f64>(key_value, "frame") {
+                            Some(frame) => frame,
+                            None => {
+                                let time: f64 = get_nested_yaml_value(key_value, "time").ok_or(
+                                    format!("key of '{}' needs a frame or time", target)
+                                )?;
+                                (first_frame as f64) + time * fps
+                            }
+                        };
+                        let value_yaml = key_value
+                            .get("value")
+                            .ok_or(format!("key of '{}' has no value", target))?;
+                        scalar = value_yaml.is_number();
+                        let value = numbers(value_yaml).ok_or(
+                            format!("key of '{}' must be a number or list of numbers", target)
+                        )?;
+                        let handle = |name: &str| -> Result<Vec<f64>, String> {
+                            let Some(handle) = key_value.get(name) else {
+                                return Ok(vec![0.0; value.len()]);
+                            };
+                            match numbers(handle) {
+                                Some(handle) if handle.len() == value.len() => Ok(handle),
+                                _ => Err(format!("'{}' handle of '{}' is wrong", name, target)),
+                            }
+                        };
+                        let handle_in = handle("in")?;
+                        let handle_out = handle("out")?;
+                        keys.push(Key { frame, value, handle_in, handle_out });
+                    }
+                }
+                if keys.is_empty() {
+                    return Err(format!("animation track '{}' has no keys", target));
+                }
+                if keys.iter().any(|key| key.value.len() != keys[0].value.len()) {
+                    return Err(format!("keys of '{}' have different sizes", target));
+                }
+                keys.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+
+                tracks.push(Track { target, interpolation, scalar, keys });
+            }
+        }
+
+        Ok(Some(Animation { first_frame, last_frame, fps, tracks, turntable }))
+    }
+
+    pub fn scene_at(&self, scene: &Value, frame: i32) -> Result<Value, String> {
+        // A copy of the scene with every animated setting set to its value at the frame.
+        let mut frame_scene = scene.clone();
+        for track in &self.tracks {
+            let value = track.evaluate(frame as f64);
+            let value = if track.scalar {
+                Value::from(value[0])
+            } else {
+                Value::Sequence(value.into_iter().map(Value::from).collect())
+            };
+            set_target(&mut frame_scene, &track.target, value)?;
+        }
+        if let Some(turntable) = &self.turntable {
+            // Each frame turns a further step, so that the camera would be back at its start
+            // one frame after the last when going all the way round.
+            let frames = (self.last_frame - self.first_frame + 1) as f64;
+            let angle = (turntable.degrees * ((frame - self.first_frame) as f64)) / frames;
+            turntable.turn(&mut frame_scene, angle)?;
+        }
+        Ok(frame_scene)
+    }
+}
+
+impl Turntable {
+    fn turn(&self, scene: &mut Value, angle: f64) -> Result<(), String> {
+        // Rotate lookfrom about the axis through lookat, using Rodrigues' rotation formula.
+        // Settings the camera section leaves out have the camera's defaults.
+        let camera = scene.get("camera").ok_or("Key 'camera' not found")?;
+        let defaults = Camera::new();
+        let vector = |key: &str, default: Vector3| {
+            get_nested_yaml_value::<[f64; 3]>(camera, key)
+                .map_or(default, |value| Vector3::new(value[0], value[1], value[2]))
+        };
+        let lookfrom = vector("lookfrom", defaults.lookfrom);
+        let lookat = vector("lookat", defaults.lookat);
+        let axis = unit_vector(self.axis.unwrap_or_else(|| vector("vup", defaults.vup)));
+
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        let offset = lookfrom - lookat;
+        let turned =
+            cos_theta * offset +
+            sin_theta * cross(axis, offset) +
+            (1.0 - cos_theta) * dot(axis, offset) * axis;
+        let position = lookat + turned;
+
+        let position = Value::Sequence(
+            vec![position.x(), position.y(), position.z()].into_iter().map(Value::from).collect()
+        );
+        set_target(scene, "camera.lookfrom", position)
+    }
+}
+
+impl Track {
+    fn evaluate(&self, frame: f64) -> Vec<f64> {
+        // Settings hold their first and last values before and after their keys.
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if frame <= keys[0].frame {
+            return keys[0].value.clone();
+        }
+        if frame >= keys[last].frame {
+            return keys[last].value.clone();
+        }
+
+        let k = keys.iter().rposition(|key| key.frame <= frame).unwrap();
+        let (k0, k1) = (&keys[k], &keys[k + 1]);
+        let t = (frame - k0.frame) / (k1.frame - k0.frame);
+
+        (0..k0.value.len())
+            .map(|c| {
+                let (p1, p2) = (k0.value[c], k1.value[c]);
+                match self.interpolation {
+                    Interpolation::Linear => p1 + (p2 - p1) * t,
+                    Interpolation::Bezier => {
+                        let c1 = p1 + k0.handle_out[c];
+                        let c2 = p2 + k1.handle_in[c];
+                        let s = 1.0 - t;
+                        s * s * s * p1 +
+                            3.0 * s * s * t * c1 +
+                            3.0 * s * t * t * c2 +
+                            t * t * t * p2
+                    }
+                    Interpolation::CatmullRom => {
+                        // The end keys are repeated to give the first and last segments a
+                        // neighbour on both sides.
+                        let p0 = keys[k.saturating_sub(1)].value[c];
+                        let p3 = keys[(k + 2).min(last)].value[c];
+                        0.5 *
+                            (2.0 * p1 +
+                                (p2 - p0) * t +
+                                (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t +
+                                (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t)
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+fn numbers(value: &Value) -> Option<Vec<f64>> {
+    match value {
+        Value::Number(number) => Some(vec![number.as_f64()?]),
+        Value::Sequence(items) => items.iter().map(|item| item.as_f64()).collect(),
+        _ => None,
+    }
+}
+
+fn set_target(scene: &mut Value, target: &str, value: Value) -> Result<(), String> {
+    // Targets are dot-separated paths such as camera.vfov, materials.ground.colour or
+    // objects.2.centre. Objects are found by their position in the list, counting from 1 as
+    // object IDs do, or by a name setting; materials are found by name.
+    let not_found = || format!("animation target '{}' is not in the scene", target);
+    let parts: Vec<&str> = target.split('.').collect();
+    let (last, path) = parts.split_last().ok_or_else(not_found)?;
+
+    let mut node = scene;
+    for part in path {
+        node = match node {
+            Value::Mapping(mapping) => mapping.get_mut(*part).ok_or_else(not_found)?,
+            Value::Sequence(items) => {
+                let position = match part.parse::<usize>() {
+                    Ok(number) if number >= 1 && number <= items.len() => number - 1,
+                    _ => {
+                        items
+                            .iter()
+                            .position(|item| {
+                                item.get("name").and_then(|name| name.as_str()) == Some(*part) ||
+                                    item.get(*part).is_some()
+                            })
+                            .ok_or_else(not_found)?
+                    }
+                };
+                let item = &mut items[position];
+                // Materials are single-key maps from their name to their settings.
+                if item.get("name").is_none() && item.get(*part).is_some() {
+                    item.get_mut(*part).unwrap()
+                } else {
+                    item
+                }
+            }
+            _ => {
+                return Err(not_found());
+            }
+        };
+    }
+
+    match node {
+        Value::Mapping(mapping) => {
+            mapping.insert(Value::from(*last), value);
+            Ok(())
+        }
+        _ => Err(not_found()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(tracks: &str) -> (Value, Animation) {
+        let scene: Value = serde_yaml::from_str(
+            &format!(
+                "camera: {{ vfov: 20.0, lookfrom: [0.0, 0.0, 4.0] }}
+materials:
+  - ground: {{ type: Lambertian, colour: [0.5, 0.5, 0.5] }}
+objects:
+  - {{ type: Sphere, name: ball, radius: 1.0 }}
+animation:
+  frames: [1, 11]
+  fps: 10
+  tracks: {}",
+                tracks
+            )
+        ).unwrap();
+        let animation = Animation::from_scene(&scene).unwrap().unwrap();
+        (scene, animation)
+    }
+
+    fn value_at(tracks: &str, target: &str, frame: i32) -> Vec<f64> {
+        let (scene, animation) = animation(tracks);
+        let frame_scene = animation.scene_at(&scene, frame).unwrap();
+        let mut node = &frame_scene;
+        for part in target.split('.') {
+            node = match node {
+                Value::Sequence(items) => &items[part.parse::<usize>().unwrap() - 1],
+                _ => node.get(part).unwrap(),
+            };
+        }
+        numbers(node).unwrap()
+    }
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn linear_moves_at_a_constant_speed() {
+        let tracks = "[{ target: camera.vfov,
+            keys: [{ frame: 1, value: 20 }, { frame: 11, value: 40 }] }]";
+        assert!(close(&value_at(tracks, "camera.vfov", 1), &[20.0]));
+        assert!(close(&value_at(tracks, "camera.vfov", 6), &[30.0]));
+        assert!(close(&value_at(tracks, "camera.vfov", 11), &[40.0]));
+    }
+
+    #[test]
+    fn values_hold_outside_the_keys() {
+        let tracks = "[{ target: camera.vfov,
+            keys: [{ frame: 3, value: 20 }, { frame: 5, value: 40 }] }]";
+        assert!(close(&value_at(tracks, "camera.vfov", 1), &[20.0]));
+        assert!(close(&value_at(tracks, "camera.vfov", 9), &[40.0]));
+    }
+
+    #[test]
+    fn times_are_converted_to_frames() {
+        // At 10 fps, 0.5 seconds after frame 1 is frame 6.
+        let tracks = "[{ target: camera.vfov,
+            keys: [{ time: 0, value: 0 }, { time: 0.5, value: 10 }] }]";
+        assert!(close(&value_at(tracks, "camera.vfov", 4), &[6.0]));
+    }
+
+    #[test]
+    fn bezier_eases_without_handles_and_follows_them() {
+        let eased = "[{ target: camera.vfov, interpolation: bezier,
+            keys: [{ frame: 1, value: 0 }, { frame: 11, value: 10 }] }]";
+        // Smoothstep: half way at the middle, slower than linear near the ends.
+        assert!(close(&value_at(eased, "camera.vfov", 6), &[5.0]));
+        assert!(close(&value_at(eased, "camera.vfov", 2), &[0.28]));
+
+        // Handles a third of the way along make the curve a straight line.
+        let straight = "[{ target: camera.vfov, interpolation: bezier,
+            keys: [{ frame: 1, value: 0, out: [10.0] }, { frame: 11, value: 30, in: [-10.0] }] }]";
+        assert!(close(&value_at(straight, "camera.vfov", 2), &[3.0]));
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_every_key() {
+        let tracks = "[{ target: camera.lookfrom, interpolation: catmull-rom, keys: [
+            { frame: 1, value: [0.0, 0.0, 4.0] },
+            { frame: 6, value: [4.0, 1.0, 0.0] },
+            { frame: 11, value: [0.0, 0.0, -4.0] }] }]";
+        assert!(close(&value_at(tracks, "camera.lookfrom", 6), &[4.0, 1.0, 0.0]));
+        // Between keys with neighbours on both sides, evenly spaced keys on a straight line
+        // stay on it.
+        let line = "[{ target: camera.vfov, interpolation: catmull-rom, keys: [
+            { frame: 1, value: 0 }, { frame: 4, value: 3 },
+            { frame: 7, value: 6 }, { frame: 10, value: 9 }] }]";
+        assert!(close(&value_at(line, "camera.vfov", 5), &[4.0]));
+    }
+
+    #[test]
+    fn targets_materials_and_objects_by_name() {
+        let tracks = "[
+            { target: materials.ground.colour, keys: [{ frame: 1, value: [1.0, 0.0, 0.0] }] },
+            { target: objects.ball.radius, keys: [{ frame: 1, value: 2.0 }] }]";
+        assert!(close(&value_at(tracks, "materials.1.ground.colour", 1), &[1.0, 0.0, 0.0]));
+        assert!(close(&value_at(tracks, "objects.1.radius", 1), &[2.0]));
+
+        let missing = "[{ target: objects.nope.radius, keys: [{ frame: 1, value: 1 }] }]";
+        let (scene, animation) = animation(missing);
+        assert!(animation.scene_at(&scene, 1).is_err());
+    }
+
+    #[test]
+    fn turntable_turns_the_camera_about_vup() {
+        // Four frames a quarter turn apart, starting from the scene's camera.
+        let lookfrom_at = |camera: &str, frame: i32| {
+            let scene: Value = serde_yaml::from_str(
+                &format!("camera: {{ turntable: 4, {} }}", camera)
+            ).unwrap();
+            let animation = Animation::from_scene(&scene).unwrap().unwrap();
+            let frame_scene = animation.scene_at(&scene, frame).unwrap();
+            numbers(&frame_scene["camera"]["lookfrom"]).unwrap()
+        };
+        let placed = "lookfrom: [0.0, 0.0, 4.0], lookat: [0.0, 0.0, 0.0]";
+        assert!(close(&lookfrom_at(placed, 1), &[0.0, 0.0, 4.0]));
+        assert!(close(&lookfrom_at(placed, 2), &[4.0, 0.0, 0.0]));
+
+        // A camera section without them turns the default camera's.
+        let defaults = Camera::new();
+        let (from, at) = (defaults.lookfrom, defaults.lookat);
+        assert!(close(&lookfrom_at("vfov: 20.0", 1), &[from.x(), from.y(), from.z()]));
+        let turned = at + Vector3::new(from.z() - at.z(), from.y() - at.y(), at.x() - from.x());
+        assert!(close(&lookfrom_at("vfov: 20.0", 2), &[turned.x(), turned.y(), turned.z()]));
+    }
+}
@@ -0,0 +1,135 @@
+use serde::Deserialize;
+
+use crate::{ colour::Colour, material::Lobe, vector3::Vector3 };
+
+// Arbitrary output variables: images written alongside the main render for compositing.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum Aov {
+    Depth, // Camera-space depth of the first hit
+    Normal, // World-space normal of the first hit, facing the camera
+    Albedo, // Reflectance of the first hit, or the sky colour where camera rays miss
+    Uv, // Surface coordinates of the first hit
+    ObjectId, // Position of the first hit object in the scene, counting from 1 (0 is the sky)
+    MaterialId, // Position of the first hit object's material in the scene, counting from 1
+    Background, // Light from camera rays that reach the sky directly
+    Direct, // Light that reaches the sky after one bounce
+    Indirect, // Light that reaches the sky after two or more bounces
+    Diffuse, // Light whose first bounce was diffuse
+    Specular, // Light whose first bounce was specular
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum AovFormat {
+    Ppm, // One image per AOV
+    Exr, // One multi-channel EXR holding the render and every AOV
+}
+
+impl AovFormat {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "ppm" => Ok(AovFormat::Ppm),
+            "exr" => Ok(AovFormat::Exr),
+            _ => Err(format!("unknown AOV format '{}'", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AovFormat::Ppm => "ppm",
+            AovFormat::Exr => "exr",
+        }
+    }
+}
+
+impl Aov {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().replace(['-', ' '], "_").as_str() {
+            "depth" | "z" => Ok(Aov::Depth),
+            "normal" | "normals" => Ok(Aov::Normal),
+            "albedo" => Ok(Aov::Albedo),
+            "uv" => Ok(Aov::Uv),
+            "object_id" => Ok(Aov::ObjectId),
+            "material_id" => Ok(Aov::MaterialId),
+            "background" => Ok(Aov::Background),
+            "direct" => Ok(Aov::Direct),
+            "indirect" => Ok(Aov::Indirect),
+            "diffuse" => Ok(Aov::Diffuse),
+            "specular" => Ok(Aov::Specular),
+            _ => Err(format!("unknown AOV '{}'", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Background => "background",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Diffuse => "diffuse",
+            Aov::Specular => "specular",
+        }
+    }
+
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    pub fn is_id(&self) -> bool {
+        // IDs can't be averaged, so they come from a pixel's first sample only.
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+
+    pub fn sample(&self, path: &PathRecord, colour: Colour) -> [f64; 3] {
+        // This AOV's channels for one camera sample whose path brought back colour.
+        let split = |included: bool| if included { colour } else { Colour::default() };
+        let value = match self {
+            Aov::Depth => {
+                return [path.depth, 0.0, 0.0];
+            }
+            Aov::Normal => path.normal,
+            Aov::Albedo => path.albedo,
+            Aov::Uv => {
+                return [path.u, path.v, 0.0];
+            }
+            Aov::ObjectId => {
+                return [path.object_id as f64, 0.0, 0.0];
+            }
+            Aov::MaterialId => {
+                return [path.material_id as f64, 0.0, 0.0];
+            }
+            Aov::Background => split(path.scatters == 0),
+            Aov::Direct => split(path.scatters == 1),
+            Aov::Indirect => split(path.scatters >= 2),
+            Aov::Diffuse => split(path.first_lobe == Some(Lobe::Diffuse)),
+            Aov::Specular => split(path.first_lobe == Some(Lobe::Specular)),
+        };
+        [value.x(), value.y(), value.z()]
+    }
+}
+
+// What one camera sample saw at its first hit, and how its path reached the sky.
+#[derive(Default)]
+pub struct PathRecord {
+    pub depth: f64,
+    pub normal: Vector3,
+    pub albedo: Colour,
+    pub u: f64,
+    pub v: f64,
+    pub object_id: i32,
+    pub material_id: i32,
+    pub scatters: i32, // Scattering events along the path so far
+    pub walk_steps: i32, // Of those, the ones inside random-walk materials such as Subsurface
+    pub first_lobe: Option<Lobe>,
+    pub debug: bool, // Print each bounce as it's traced
+}
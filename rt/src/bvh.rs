@@ -1,4 +1,4 @@
-// use std::{ rc::Rc, cmp::Ordering };
+// use std::{ sync::Arc, cmp::Ordering };
 
 // use crate::{
 //     hittable::Hittable,
@@ -9,16 +9,16 @@
 // };
 
 // pub struct BvhNode {
-//     left: Rc<dyn Hittable>,
-//     right: Rc<dyn Hittable>,
+//     left: Arc<dyn Hittable>,
+//     right: Arc<dyn Hittable>,
 //     bbox: Aabb,
 // }
 
 // impl BvhNode {
-//     pub fn new(src_objects: Vec<Rc<dyn Hittable>>, start: usize, end: usize) -> Self {
+//     pub fn new(src_objects: Vec<Arc<dyn Hittable>>, start: usize, end: usize) -> Self {
 //         let mut new_bvh = BvhNode {
-//             left: Rc::new(BvhNode::from_list(HittableList::new())),
-//             right: Rc::new(BvhNode::from_list(HittableList::new())),
+//             left: Arc::new(BvhNode::from_list(HittableList::new())),
+//             right: Arc::new(BvhNode::from_list(HittableList::new())),
 //             bbox: Aabb::default(),
 //         };
 //         let mut objects = src_objects; // Create a modifiable array of the source scene objects
@@ -49,8 +49,8 @@
 //             objects[start..end].sort_by(|a, b| comparator(a.clone(), b.clone()));
 
 //             let mid = start + object_span / 2;
-//             new_bvh.left = Rc::new(BvhNode::new(objects.clone(), start, mid));
-//             new_bvh.right = Rc::new(BvhNode::new(objects.clone(), mid, end));
+//             new_bvh.left = Arc::new(BvhNode::new(objects.clone(), start, mid));
+//             new_bvh.right = Arc::new(BvhNode::new(objects.clone(), mid, end));
 //         }
 
 //         new_bvh.bbox = Aabb::from_boxes(new_bvh.left.bounding_box(), new_bvh.right.bounding_box());
@@ -62,7 +62,7 @@
 //         BvhNode::new(list.objects.clone(), 0, list.objects.len())
 //     }
 
-//     fn box_compare(a: Rc<dyn Hittable>, b: Rc<dyn Hittable>, axis_index: i32) -> Ordering {
+//     fn box_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>, axis_index: i32) -> Ordering {
 //         let a_min = a.bounding_box().axis(axis_index).min;
 //         let b_min = b.bounding_box().axis(axis_index).min;
 
@@ -75,15 +75,15 @@
 //         }
 //     }
 
-//     fn box_x_compare(a: Rc<dyn Hittable>, b: Rc<dyn Hittable>) -> Ordering {
+//     fn box_x_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> Ordering {
 //         return Self::box_compare(a, b, 0);
 //     }
 
-//     fn box_y_compare(a: Rc<dyn Hittable>, b: Rc<dyn Hittable>) -> Ordering {
+//     fn box_y_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> Ordering {
 //         return Self::box_compare(a, b, 1);
 //     }
 
-//     fn box_z_compare(a: Rc<dyn Hittable>, b: Rc<dyn Hittable>) -> Ordering {
+//     fn box_z_compare(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> Ordering {
 //         return Self::box_compare(a, b, 2);
 //     }
 // }
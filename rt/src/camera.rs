@@ -1,18 +1,35 @@
-use std::{ f64::INFINITY, fs::File, io::{ self, Write } };
+use std::{
+    cell::Cell,
+    sync::{ mpsc, Arc, MutexGuard },
+    thread,
+    time::{ Duration, Instant },
+};
 use serde::Deserialize;
-use serde_yaml::Value;
+use serde_yaml::{ Mapping, Value };
 
 use crate::{
+    aov::{ Aov, AovFormat, PathRecord },
     hittable::{ Hittable, HitRecord },
     ray::Ray,
-    colour::{ Colour, write_colour },
+    colour::Colour,
+    film::{ Film, Framebuffer, Region },
+    filter::Filter,
     interval::Interval,
-    vector3::{ unit_vector, Point3, Vector3, cross },
-    rtweekend::{ random_f64, degrees_to_radians },
-    helper::get_nested_yaml_value,
+    vector3::{ unit_vector, Point3, Vector3, cross, dot },
+    rtweekend::degrees_to_radians,
+    sampler::{ Sampler, SamplerKind },
+    helper::{ get_nested_yaml_value, parse_duration },
+    denoise::denoise,
+    checkpoint::{ Checkpoint, hash_scene },
+    rtweekend::start_sample_stream,
+    tile::{ splat_bounds, tiles, TileJob, TileOrder, TileQueue, TileResult },
+    distributed::{ Cluster, SceneSetup },
+    stats::{ self, PathEnd, RenderStats, SharedStats },
+    progress::{ Progress, ProgressStyle },
+    registry::registered_types,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Camera {
     pub aspect_ratio: f64, // Ratio of image width over height
     pub image_width: i32, // Rendered image width in pixel count
@@ -23,6 +40,42 @@ pub struct Camera {
     pub lookat: Point3, // Point camera is looking at
     pub vup: Vector3, // Camera-relative "up" direction
     pub brightness: f64,
+    pub noise_threshold: f64, // Per-pixel error at which adaptive sampling stops (0 disables it)
+    pub min_samples: i32, // Samples every pixel gets before adaptive sampling may stop it
+    pub max_samples: i32, // Most samples adaptive sampling will spend on one pixel
+    pub sample_heatmap: bool, // Also write an image of the sample count per pixel
+    pub progressive: bool, // Render one sample per pixel per pass over the whole image
+    pub snapshot_passes: i32, // In progressive mode, write the image every this many passes
+    pub snapshot_seconds: f64, // In progressive mode, write the image every this many seconds
+    pub time_limit: Option<Duration>, // In progressive mode, stop once this much time has passed
+    pub checkpoint: Option<String>, // In progressive mode, file to save resumable progress to
+    pub checkpoint_seconds: f64, // Save the checkpoint every this many seconds
+    pub seed: Option<u64>, // Seed for the random numbers, or None for a different render every time
+    pub sampler: SamplerKind, // How sample values are spread over each pixel's samples
+    pub filter: Filter, // Reconstruction filter that weights samples into the pixels around them
+    pub aovs: Vec<Aov>, // Extra outputs to write alongside the render
+    pub aov_format: AovFormat, // Whether AOVs are written as separate images or one EXR
+    pub denoise: bool, // Denoise the image before writing it
+    pub denoise_raw: bool, // When denoising, also write the image as rendered
+    pub region: Option<Region>, // Only render the pixels in this rectangle
+    pub crop: bool, // Write only the region, rather than the full image with the rest black
+    pub debug_pixel: Option<(i32, i32)>, // Trace this pixel's samples and print every bounce
+    pub tile_size: i32, // Width and height of the tiles the image is rendered in
+    pub tile_order: TileOrder, // Order the tiles are handed out in
+    pub threads: usize, // Threads rendering tiles at once (0 uses every available core)
+    pub workers: Vec<String>, // Addresses of worker processes to share the tiles between
+    pub stats_json: bool, // Also write the render statistics as JSON
+
+    scene_path: String, // Scene file this camera was read from
+    scene: String, // Contents of the scene, after any animation has been applied
+    scene_hash: u64, // Hash of the scene file's contents, to match checkpoints to scenes
+    sample_seed: u64, // Seed in use for this render
+    #[serde(skip)]
+    cluster: Option<Arc<Cluster>>, // Connections to the workers
+    #[serde(skip)]
+    stats: SharedStats, // Statistics of the current render, added to by each thread
+    #[serde(skip)]
+    progress: Progress, // Reports how far the render has got
 
     image_height: i32, // Rendered image height
     centre: Point3, // camera centre
@@ -34,18 +87,58 @@ pub struct Camera {
     w: Vector3, // Camera frame basis vectors
 }
 
+impl Default for Camera {
+    fn default() -> Self {
+        Camera::new()
+    }
+}
+
 impl Camera {
     pub fn new() -> Self {
+        // The settings a scene's camera section gets when it leaves everything out.
         Camera {
-            aspect_ratio: 0.0,
-            image_width: 0,
-            samples_per_pixel: 10,
-            max_depth: 10,
-            vfov: 90.0,
-            lookfrom: Point3::new(0.0, 0.0, -1.0),
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 800,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            vfov: 20.0,
+            lookfrom: Point3::new(1.0, 1.0, 1.0),
             lookat: Point3::default(),
             vup: Vector3::new(0.0, 1.0, 0.0),
             brightness: 1.0,
+            noise_threshold: 0.0,
+            min_samples: 16,
+            max_samples: 100,
+            sample_heatmap: false,
+            progressive: false,
+            snapshot_passes: 0,
+            snapshot_seconds: 0.0,
+            time_limit: None,
+            checkpoint: None,
+            checkpoint_seconds: 300.0,
+            seed: None,
+            sampler: SamplerKind::Independent,
+            filter: Filter::default(),
+            aovs: Vec::new(),
+            aov_format: AovFormat::Ppm,
+            denoise: false,
+            denoise_raw: false,
+            region: None,
+            crop: false,
+            debug_pixel: None,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            threads: 0,
+            workers: Vec::new(),
+            stats_json: false,
+
+            scene_path: String::new(),
+            scene: String::new(),
+            scene_hash: 0,
+            sample_seed: 0,
+            cluster: None,
+            stats: SharedStats::default(),
+            progress: Progress::default(),
 
             image_height: 0,
             centre: Point3::default(),
@@ -60,27 +153,115 @@ impl Camera {
 
     pub fn from_yaml_file(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let yaml_content = std::fs::read_to_string(file_path)?;
-        let yaml_value: Value = serde_yaml::from_str(&yaml_content)?;
+        Camera::from_yaml_str(file_path, &yaml_content)
+    }
+
+    pub fn from_yaml_str(
+        file_path: &str,
+        yaml_content: &str
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let yaml_value: Value = serde_yaml::from_str(yaml_content)?;
 
         // Access the 'camera' key
         if let Some(camera_value) = yaml_value.get("camera") {
-            // Access values inside the 'camera' key
+            // Access values inside the 'camera' key, falling back on the defaults
+            let defaults = Camera::new();
+            let vector = |key: &str, default: Vector3| {
+                get_nested_yaml_value::<[f64; 3]>(camera_value, key)
+                    .map(|v| Vector3::new(v[0], v[1], v[2]))
+                    .unwrap_or(default)
+            };
             let aspect_ratio = get_nested_yaml_value(camera_value, "aspect_ratio").unwrap_or(
-                16.0 / 9.0
+                defaults.aspect_ratio
+            );
+            let image_width = get_nested_yaml_value(camera_value, "image_width").unwrap_or(
+                defaults.image_width
             );
-            let image_width = get_nested_yaml_value(camera_value, "image_width").unwrap_or(800);
             let samples_per_pixel = get_nested_yaml_value(
                 camera_value,
                 "samples_per_pixel"
-            ).unwrap_or(100);
-            let max_depth = get_nested_yaml_value(camera_value, "max_depth").unwrap_or(50);
-            let vfov = get_nested_yaml_value(camera_value, "vfov").unwrap_or(20.0);
-            let lookfrom = get_nested_yaml_value(camera_value, "lookfrom").unwrap_or([
-                1.0, 1.0, 1.0,
-            ]);
-            let lookat = get_nested_yaml_value(camera_value, "lookat").unwrap_or([0.0, 0.0, 0.0]);
-            let vup = get_nested_yaml_value(camera_value, "vup").unwrap_or([0.0, 1.0, 0.0]);
-            let brightness = get_nested_yaml_value(camera_value, "brightness").unwrap_or(1.0);
+            ).unwrap_or(defaults.samples_per_pixel);
+            let max_depth = get_nested_yaml_value(camera_value, "max_depth").unwrap_or(
+                defaults.max_depth
+            );
+            let vfov = get_nested_yaml_value(camera_value, "vfov").unwrap_or(defaults.vfov);
+            let lookfrom = vector("lookfrom", defaults.lookfrom);
+            let lookat = vector("lookat", defaults.lookat);
+            let vup = vector("vup", defaults.vup);
+            let brightness = get_nested_yaml_value(camera_value, "brightness").unwrap_or(
+                defaults.brightness
+            );
+            let noise_threshold = get_nested_yaml_value(camera_value, "noise_threshold").unwrap_or(
+                defaults.noise_threshold
+            );
+            let min_samples = get_nested_yaml_value(camera_value, "min_samples").unwrap_or(
+                defaults.min_samples
+            );
+            // Without a cap of its own, adaptive sampling stops at the requested sample count.
+            let max_samples = get_nested_yaml_value(camera_value, "max_samples").unwrap_or(
+                samples_per_pixel
+            );
+            let sample_heatmap = get_nested_yaml_value(camera_value, "sample_heatmap").unwrap_or(
+                defaults.sample_heatmap
+            );
+            let snapshot_passes = get_nested_yaml_value(camera_value, "snapshot_passes").unwrap_or(
+                defaults.snapshot_passes
+            );
+            let snapshot_seconds = get_nested_yaml_value(
+                camera_value,
+                "snapshot_seconds"
+            ).unwrap_or(defaults.snapshot_seconds);
+            let time_limit = match get_nested_yaml_value::<String>(camera_value, "time_limit") {
+                Some(text) => Some(parse_duration(&text)?),
+                None => None,
+            };
+            let checkpoint: Option<String> = get_nested_yaml_value(camera_value, "checkpoint");
+            let checkpoint_seconds = get_nested_yaml_value(
+                camera_value,
+                "checkpoint_seconds"
+            ).unwrap_or(defaults.checkpoint_seconds);
+            let seed = get_nested_yaml_value(camera_value, "seed");
+            let sampler = match get_nested_yaml_value::<String>(camera_value, "sampler") {
+                Some(name) => SamplerKind::from_name(&name)?,
+                None => SamplerKind::Independent,
+            };
+            let filter_radius = get_nested_yaml_value(camera_value, "filter_radius");
+            let filter = match get_nested_yaml_value::<String>(camera_value, "filter") {
+                Some(name) => Filter::from_name(&name, filter_radius)?,
+                None => Filter::default(),
+            };
+            let aovs = get_nested_yaml_value::<Vec<String>>(camera_value, "aovs")
+                .unwrap_or_default()
+                .iter()
+                .map(|name| Aov::from_name(name))
+                .collect::<Result<Vec<Aov>, String>>()?;
+            let aov_format = match get_nested_yaml_value::<String>(camera_value, "aov_format") {
+                Some(name) => AovFormat::from_name(&name)?,
+                None => AovFormat::Ppm,
+            };
+            let denoise = get_nested_yaml_value(camera_value, "denoise").unwrap_or(
+                defaults.denoise
+            );
+            let denoise_raw = get_nested_yaml_value(camera_value, "denoise_raw").unwrap_or(
+                defaults.denoise_raw
+            );
+            let tile_size = get_nested_yaml_value(camera_value, "tile_size").unwrap_or(
+                defaults.tile_size
+            );
+            let tile_order = match get_nested_yaml_value::<String>(camera_value, "tile_order") {
+                Some(name) => TileOrder::from_name(&name)?,
+                None => TileOrder::Spiral,
+            };
+            let threads = get_nested_yaml_value(camera_value, "threads").unwrap_or(
+                defaults.threads
+            );
+            let stats_json = get_nested_yaml_value(camera_value, "stats_json").unwrap_or(
+                defaults.stats_json
+            );
+            // Checkpoints store progressive passes, so asking for one implies progressive mode.
+            let progressive = get_nested_yaml_value(camera_value, "progressive").unwrap_or(
+                checkpoint.is_some()
+            );
 
             // Create a Camera instance using extracted values
             let camera = Camera {
@@ -89,10 +270,43 @@ impl Camera {
                 samples_per_pixel,
                 max_depth,
                 vfov,
-                lookfrom: Vector3::new(lookfrom[0], lookfrom[1], lookfrom[2]),
-                lookat: Vector3::new(lookat[0], lookat[1], lookat[2]),
-                vup: Vector3::new(vup[0], vup[1], vup[2]),
+                lookfrom,
+                lookat,
+                vup,
                 brightness,
+                noise_threshold,
+                min_samples,
+                max_samples,
+                sample_heatmap,
+                progressive,
+                seed,
+                sampler,
+                filter,
+                aovs,
+                aov_format,
+                denoise,
+                denoise_raw,
+                region: None,
+                crop: false,
+                debug_pixel: None,
+                tile_size,
+                tile_order,
+                threads,
+                workers: Vec::new(),
+                stats_json,
+                snapshot_passes,
+                snapshot_seconds,
+                time_limit,
+                checkpoint,
+                checkpoint_seconds,
+
+                scene_path: file_path.to_string(),
+                scene: yaml_content.to_string(),
+                scene_hash: hash_scene(yaml_content),
+                sample_seed: 0,
+                cluster: None,
+                stats: SharedStats::default(),
+                progress: Progress::default(),
 
                 image_height: 0,
                 centre: Point3::default(),
@@ -110,35 +324,682 @@ impl Camera {
         }
     }
 
-    pub fn render(&mut self, world: &dyn Hittable, filename: String) {
+    pub fn to_yaml(&self) -> Value {
+        // The camera section with every setting the camera was read with, including defaults.
+        let vector = |v: Vector3| serde_yaml::to_value([v.x(), v.y(), v.z()]).unwrap();
+        let mut camera = Mapping::new();
+        let mut set = |key: &str, value: Value| {
+            camera.insert(Value::from(key), value);
+        };
+        set("aspect_ratio", Value::from(self.aspect_ratio));
+        set("image_width", Value::from(self.image_width));
+        set("samples_per_pixel", Value::from(self.samples_per_pixel));
+        set("max_depth", Value::from(self.max_depth));
+        set("vfov", Value::from(self.vfov));
+        set("lookfrom", vector(self.lookfrom));
+        set("lookat", vector(self.lookat));
+        set("vup", vector(self.vup));
+        set("brightness", Value::from(self.brightness));
+        set("noise_threshold", Value::from(self.noise_threshold));
+        set("min_samples", Value::from(self.min_samples));
+        set("max_samples", Value::from(self.max_samples));
+        set("sample_heatmap", Value::from(self.sample_heatmap));
+        set("progressive", Value::from(self.progressive));
+        set("snapshot_passes", Value::from(self.snapshot_passes));
+        set("snapshot_seconds", Value::from(self.snapshot_seconds));
+        if let Some(limit) = self.time_limit {
+            set("time_limit", Value::from(format!("{}s", limit.as_secs_f64())));
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            set("checkpoint", Value::from(checkpoint.as_str()));
+            set("checkpoint_seconds", Value::from(self.checkpoint_seconds));
+        }
+        if let Some(seed) = self.seed {
+            set("seed", Value::from(seed));
+        }
+        set("sampler", Value::from(self.sampler.name()));
+        set("filter", Value::from(self.filter.name()));
+        set("filter_radius", Value::from(self.filter.radius));
+        let aovs: Vec<&str> = self.aovs.iter().map(|aov| aov.name()).collect();
+        set("aovs", serde_yaml::to_value(aovs).unwrap());
+        set("aov_format", Value::from(self.aov_format.name()));
+        set("denoise", Value::from(self.denoise));
+        set("denoise_raw", Value::from(self.denoise_raw));
+        set("tile_size", Value::from(self.tile_size));
+        set("tile_order", Value::from(self.tile_order.name()));
+        set("threads", Value::from(self.threads as u64));
+        set("stats_json", Value::from(self.stats_json));
+        Value::Mapping(camera)
+    }
+
+    pub fn render(
+        &mut self,
+        world: &dyn Hittable,
+        filename: String
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(film) = self.render_film(world, &filename)? {
+            self.write_images(&film, filename);
+        }
+        Ok(())
+    }
+
+    pub fn render_framebuffer(
+        &mut self,
+        world: &dyn Hittable,
+        filename: &str
+    ) -> Result<Framebuffer, Box<dyn std::error::Error>> {
+        // Render the image into memory instead of writing it. Snapshots, checkpoints and
+        // statistics the camera asks for are still written, named after filename.
+        if self.debug_pixel.is_some() {
+            return Err("a debug pixel trace has no image to return".into());
+        }
+        let film = self.render_film(world, filename)?.ok_or("nothing was rendered")?;
+
+        let write_start = Instant::now();
+        let region_film = self.region_film(&film);
+        let film = region_film.as_ref().unwrap_or(&film);
+        let framebuffer = Framebuffer {
+            width: film.width,
+            height: film.height,
+            pixels: self.final_colours(film),
+        };
+        self.finish(write_start, filename);
+        Ok(framebuffer)
+    }
+
+    fn render_film(
+        &mut self,
+        world: &dyn Hittable,
+        filename: &str
+    ) -> Result<Option<Film>, Box<dyn std::error::Error>> {
+        // Render the image into a film, or only trace the debug pixel, returning no film.
+        self.initialise();
+        self.sample_seed = self.seed.unwrap_or_else(rand::random);
+
+        self.clamp_region()?;
+        if let Some((i, j)) = self.debug_pixel {
+            if i < 0 || i >= self.image_width || j < 0 || j >= self.image_height {
+                return Err("debug pixel is outside the image".into());
+            }
+            self.debug_trace(world, i, j);
+            return Ok(None);
+        }
+
+        self.progress.message(
+            &format!("Image width: {}, height: {}", self.image_width, self.image_height)
+        );
+        self.connect_workers()?;
+
+        let mut film = Film::new(
+            self.image_width,
+            self.image_height,
+            self.filter,
+            self.film_aovs()
+        );
+
+        let render_start = Instant::now();
+        self.progress.start(
+            self.image_width,
+            self.image_height,
+            self.target_samples(),
+            self.time_limit.filter(|_| self.progressive)
+        );
+        if self.progressive {
+            self.render_progressive(world, &mut film, filename, 0);
+        } else if self.noise_threshold > 0.0 {
+            self.render_adaptive(world, &mut film);
+        } else {
+            let active = self.region_mask();
+            let samples = self.samples_per_pixel;
+            self.render_pass(world, &mut film, samples, &active, (0.0, 1.0), &mut |_| {});
+        }
+        self.stats().render = render_start.elapsed();
+
+        Ok(Some(film))
+    }
+
+    pub fn resume(
+        &mut self,
+        world: &dyn Hittable,
+        filename: String,
+        checkpoint: Checkpoint,
+        mut film: Film
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Continue a progressive render from a checkpoint of the same scene. Reusing its seed
+        // means the passes that follow are the ones an uninterrupted render would have taken.
         self.initialise();
+        self.clamp_region()?;
+
+        if checkpoint.scene_hash != self.scene_hash {
+            return Err(
+                format!(
+                    "scene '{}' has changed since the checkpoint was written",
+                    self.scene_path
+                ).into()
+            );
+        }
+        if film.width != self.image_width || film.height != self.image_height {
+            return Err("checkpoint image size does not match the scene".into());
+        }
 
-        let mut image_file = File::create(filename + ".ppm").expect("file creation failed");
+        self.progress.message(&format!("Resuming after {} passes", checkpoint.passes));
 
-        println!(
-            "P3\nImage width: {}\nImage height: {}\n255\n",
+        self.sample_seed = checkpoint.seed;
+        self.progressive = true;
+        self.connect_workers()?;
+        film.filter = self.filter;
+        film.aovs = self.film_aovs();
+        if film.aov_data.len() != film.pixel_count() * film.aov_channels() {
+            return Err("checkpoint AOVs do not match the scene".into());
+        }
+        let render_start = Instant::now();
+        self.progress.start(
             self.image_width,
-            self.image_height
+            self.image_height,
+            self.target_samples(),
+            self.time_limit
         );
+        self.render_progressive(world, &mut film, &filename, checkpoint.passes);
+        self.stats().render = render_start.elapsed();
+
+        self.write_images(&film, filename);
+        Ok(())
+    }
+
+    pub fn set_load_times(&mut self, parse: Duration, build: Duration) {
+        // How long reading the scene and building the world took, for the render statistics.
+        let stats = self.stats.get_mut();
+        stats.parse = parse;
+        stats.build = build;
+    }
+
+    fn stats(&self) -> MutexGuard<'_, RenderStats> {
+        self.stats.lock()
+    }
 
-        image_file
-            .write(format!("P3\n{} {}\n255\n", self.image_width, self.image_height).as_bytes())
-            .expect("write failed");
+    pub fn set_progress_style(&mut self, style: ProgressStyle) {
+        self.progress = Progress::new(style);
+    }
+
+    pub fn image_height(&self) -> i32 {
+        self.image_height
+    }
+
+    pub fn initialise_worker(&mut self, seed: u64) {
+        // Set up the camera to render tiles for a coordinator using the same seed.
+        self.initialise();
+        self.sample_seed = seed;
+    }
 
+    fn connect_workers(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Send the scene to the workers, which must be able to render exactly what this process
+        // would: the same scene, seed and sample counts.
+        if self.workers.is_empty() {
+            return Ok(());
+        }
+        let scene = self.worker_scene()?;
+        let (material_types, object_types) = registered_types(&serde_yaml::from_str(&scene)?);
+        let setup = SceneSetup {
+            scene_path: self.scene_path.clone(),
+            scene,
+            seed: self.sample_seed,
+            samples_per_pixel: self.samples_per_pixel,
+            max_samples: self.max_samples,
+            material_types,
+            object_types,
+        };
+        self.cluster = Some(Arc::new(Cluster::connect(&self.workers, &setup, &self.progress)));
+        Ok(())
+    }
+
+    fn worker_scene(&self) -> Result<String, Box<dyn std::error::Error>> {
+        // The scene file with this camera's settings in place of its camera section, as they may
+        // have been changed since the file was read. Workers keep the file's thread count, as
+        // they may not have as many cores as this machine.
+        if self.scene.is_empty() {
+            return Err("workers can only render scenes read from a file".into());
+        }
+        let mut scene: Value = serde_yaml::from_str(&self.scene)?;
+        let mut camera = self.to_yaml();
+        camera["threads"] = scene["camera"]["threads"].clone();
+        scene["camera"] = camera;
+        Ok(serde_yaml::to_string(&scene)?)
+    }
+
+    fn film_aovs(&self) -> Vec<Aov> {
+        // The AOVs the film records: the ones asked for, and the denoiser's guides.
+        let mut aovs = self.aovs.clone();
+        if self.denoise {
+            for guide in [Aov::Albedo, Aov::Normal] {
+                if !aovs.contains(&guide) {
+                    aovs.push(guide);
+                }
+            }
+        }
+        aovs
+    }
+
+    fn clamp_region(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(region) = self.region {
+            let clamped = region.clamp(self.image_width, self.image_height);
+            self.region = Some(clamped.ok_or("region is outside the image")?);
+        }
+        Ok(())
+    }
+
+    fn region_mask(&self) -> Vec<bool> {
+        // Which pixels to render.
+        let mut mask = Vec::with_capacity((self.image_width * self.image_height) as usize);
         for j in 0..self.image_height {
-            print!("\rScanlines remaining: {} ", self.image_height - j);
-            io::stdout().flush().unwrap();
             for i in 0..self.image_width {
-                let mut pixel_colour = Colour::default();
-                for _ in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    pixel_colour += self.ray_colour(&r, self.max_depth, world);
+                mask.push(self.region.is_none_or(|region| region.contains(i, j)));
+            }
+        }
+        mask
+    }
+
+    fn region_film(&self, film: &Film) -> Option<Film> {
+        // The film to write when rendering a region: either just the region, or the full image
+        // with everything outside it cleared.
+        let region = self.region?;
+        if self.crop {
+            return Some(film.cropped(&region));
+        }
+        Some(film.masked(&region))
+    }
+
+    fn write_images(&self, film: &Film, filename: String) {
+        let write_start = Instant::now();
+        let region_film = self.region_film(film);
+        let film = region_film.as_ref().unwrap_or(film);
+
+        if self.denoise && self.denoise_raw {
+            film.write_ppm(&(filename.clone() + "_raw")).expect("write failed");
+        }
+        let colours = self.final_colours(film);
+        film.write_colours(&filename, &colours).expect("write failed");
+
+        if !self.aovs.is_empty() || self.aov_format == AovFormat::Exr {
+            match self.aov_format {
+                AovFormat::Ppm => film.write_aov_ppms(&filename, &self.aovs).expect("write failed"),
+                AovFormat::Exr => film.write_exr(&filename, &colours, &self.aovs).expect("write failed"),
+            }
+        }
+        if self.sample_heatmap {
+            film.write_sample_heatmap(&(filename.clone() + "_samples")).expect("write failed");
+        }
+
+        self.finish(write_start, &filename);
+    }
+
+    fn final_colours(&self, film: &Film) -> Vec<Colour> {
+        // The film's colours, denoised if the camera asks for it.
+        if self.denoise {
+            self.progress.message("Denoising...");
+            return denoise(film);
+        }
+        film.colours()
+    }
+
+    fn finish(&self, write_start: Instant, filename: &str) {
+        // Report the end of the render and its statistics.
+        self.progress.message("Done.");
+
+        let mut stats = self.stats();
+        stats.write = write_start.elapsed();
+        stats.threads = self.thread_count();
+        stats.max_depth = self.max_depth;
+        // JSON progress has the statistics in its done event instead.
+        if self.progress.style == ProgressStyle::Text {
+            stats.print();
+        }
+        if self.stats_json {
+            stats.write_json(filename).expect("write failed");
+        }
+        self.progress.finish(&stats);
+    }
+
+    fn save_checkpoint(&self, film: &Film, passes: i32) {
+        if let Some(path) = &self.checkpoint {
+            let checkpoint = Checkpoint {
+                scene_path: self.scene_path.clone(),
+                scene_hash: self.scene_hash,
+                passes,
+                seed: self.sample_seed,
+            };
+            checkpoint.save(path, film).expect("checkpoint write failed");
+        }
+    }
+
+    fn write_snapshot(&self, film: &Film, filename: &str) {
+        let region_film = self.region_film(film);
+        region_film.as_ref().unwrap_or(film).write_ppm(filename).expect("write failed");
+    }
+
+    fn render_progressive(
+        &self,
+        world: &dyn Hittable,
+        film: &mut Film,
+        filename: &str,
+        first_pass: i32
+    ) {
+        // Render one sample per pixel per pass over the whole image, so the complete image
+        // converges together, writing snapshots as it goes. Rendering stops at the target sample
+        // count, when the time budget runs out, or when adaptive sampling has stopped every pixel.
+        let target_samples = self.target_samples();
+        let in_region = self.region_mask();
+        let adaptive_stop = |film: &Film, active: &mut Vec<bool>, passes: i32| {
+            if self.noise_threshold > 0.0 && passes >= self.min_samples.max(2) {
+                for (index, pixel_active) in active.iter_mut().enumerate() {
+                    *pixel_active =
+                        in_region[index] && film.pixel_at(index).error() > self.noise_threshold;
+                }
+            }
+        };
+
+        let mut active = in_region.clone();
+        adaptive_stop(film, &mut active, first_pass);
+
+        let start = Instant::now();
+        let last_snapshot = Cell::new(start);
+        let mut last_checkpoint = start;
+        let mut passes = first_pass;
+        let span = (target_samples - first_pass).max(1) as f64;
+        while passes < target_samples && active.contains(&true) {
+            // Timed snapshots are written as soon as they are due, partway through a pass if
+            // need be, so they show the tiles filling in in tile order.
+            let mut snapshot_due = |film: &Film| {
+                if
+                    self.snapshot_seconds > 0.0 &&
+                    last_snapshot.get().elapsed().as_secs_f64() >= self.snapshot_seconds
+                {
+                    self.write_snapshot(film, filename);
+                    last_snapshot.set(Instant::now());
+                    self.progress.message(&format!("Wrote snapshot during pass {}", passes + 1));
+                }
+            };
+            let done = (passes - first_pass) as f64;
+            let span = (done / span, (done + 1.0) / span);
+            self.render_pass(world, film, 1, &active, span, &mut snapshot_due);
+            passes += 1;
+            adaptive_stop(film, &mut active, passes);
+
+            if let Some(limit) = self.time_limit {
+                if start.elapsed() >= limit {
+                    self.progress.message(
+                        &format!("Time limit reached after {} passes", passes)
+                    );
+                    break;
                 }
-                write_colour(&mut image_file, pixel_colour, self.samples_per_pixel);
+            }
+
+            if self.snapshot_passes > 0 && passes % self.snapshot_passes == 0 {
+                self.write_snapshot(film, filename);
+                last_snapshot.set(Instant::now());
+                self.progress.message(&format!("Wrote snapshot after {} passes", passes));
+            }
+
+            // Checkpoints keep their own schedule, so a render without snapshots still saves
+            // its progress.
+            if last_checkpoint.elapsed().as_secs_f64() >= self.checkpoint_seconds {
+                self.save_checkpoint(film, passes);
+                last_checkpoint = Instant::now();
             }
         }
 
-        println!("\rDone.                 \n");
+        self.save_checkpoint(film, passes);
+    }
+
+    fn render_adaptive(&self, world: &dyn Hittable, film: &mut Film) {
+        // Render in passes, and after each pass stop sampling the pixels whose estimated error
+        // has fallen below the noise threshold.
+        let min_samples = self.min_samples.max(2);
+        let max_samples = self.max_samples.max(min_samples);
+        let in_region = self.region_mask();
+        let mut active = in_region.clone();
+
+        let mut samples = min_samples;
+        let mut pass_samples = min_samples;
+        loop {
+            // The pass covers the samples up to samples, out of at most max_samples.
+            let span = (
+                ((samples - pass_samples) as f64) / (max_samples as f64),
+                (samples as f64) / (max_samples as f64),
+            );
+            self.render_pass(world, film, pass_samples, &active, span, &mut |_| {});
+
+            let mut remaining = 0;
+            for (index, pixel_active) in active.iter_mut().enumerate() {
+                let pixel = film.pixel_at(index);
+                *pixel_active =
+                    in_region[index] &&
+                    pixel.samples < max_samples &&
+                    pixel.error() > self.noise_threshold;
+                if *pixel_active {
+                    remaining += 1;
+                }
+            }
+            if remaining == 0 {
+                break;
+            }
+
+            self.progress.message(
+                &format!("{} pixels above the noise threshold after {} samples", remaining, samples)
+            );
+
+            // Each pass doubles the sample count of the pixels still active.
+            pass_samples = samples.min(max_samples - samples);
+            samples += pass_samples;
+        }
+    }
+
+    fn target_samples(&self) -> i32 {
+        // The most samples any pixel will get.
+        if self.noise_threshold > 0.0 { self.max_samples } else { self.samples_per_pixel }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        if self.threads > 0 {
+            return self.threads;
+        }
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    }
+
+    fn render_pass(
+        &self,
+        world: &dyn Hittable,
+        film: &mut Film,
+        samples: i32,
+        active: &[bool],
+        (from, to): (f64, f64),
+        on_merge: &mut dyn FnMut(&Film)
+    ) {
+        // Each tile with an active pixel is rendered into a film of its own, by local threads or
+        // by the workers when rendering across processes. Each tile film is merged
+        // as soon as every tile before it has been, so the image fills in in tile order and is
+        // the same however the work was shared out. on_merge sees the film after each merge.
+        // The pass takes the render from the fraction from of the way through to to.
+        let jobs: Vec<TileJob> = tiles(
+            self.image_width,
+            self.image_height,
+            self.tile_size,
+            self.tile_order
+        )
+            .into_iter()
+            .filter_map(|tile| {
+                let mut first_samples = Vec::new();
+                for j in tile.y0..tile.y1 {
+                    for i in tile.x0..tile.x1 {
+                        let pixel_active = active[(j * self.image_width + i) as usize];
+                        first_samples.push(if pixel_active { film.pixel(i, j).samples } else { -1 });
+                    }
+                }
+                if !first_samples.iter().any(|first| *first >= 0) {
+                    return None;
+                }
+                Some(TileJob { tile, samples, first_samples })
+            })
+            .collect();
+
+        let before = film.clone();
+        let mut results: Vec<Option<TileResult>> = jobs.iter().map(|_| None).collect();
+        self.progress.start_pass(from, to, jobs.len());
+        let mut merged = 0;
+        let mut merge_ready = |results: &[Option<TileResult>], film: &mut Film| {
+            let first = merged;
+            while let Some(((x0, y0), tile_film)) = results.get(merged).and_then(Option::as_ref) {
+                film.merge(tile_film, *x0, *y0);
+                merged += 1;
+            }
+            if merged > first {
+                on_merge(film);
+            }
+        };
+
+        // The local threads and the connections to the workers all take tiles from one queue.
+        let queue = TileQueue::new(0..jobs.len());
+        let aovs = self.film_aovs();
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            if let Some(cluster) = &self.cluster {
+                let image_size = (self.image_width, self.image_height);
+                let film_setup = (self.filter, aovs.as_slice(), image_size);
+                cluster.render(scope, &jobs, &queue, film_setup, &self.progress, &sender);
+            }
+            self.render_jobs(scope, world, &jobs, &queue, sender);
+            for (index, result) in receiver {
+                results[index] = Some(result);
+                merge_ready(&results, film);
+            }
+        });
+
+        // Neighbouring tiles splat into each other's margins, and floating point sums depend on
+        // the order they're added in, so the pass is merged again in order of tile position to
+        // give the same image whatever order the tiles were rendered in.
+        let mut order: Vec<usize> = (0..jobs.len()).collect();
+        order.sort_by_key(|&index| (jobs[index].tile.y0, jobs[index].tile.x0));
+        *film = before;
+        for index in order {
+            if let Some(((x0, y0), tile_film)) = &results[index] {
+                film.merge(tile_film, *x0, *y0);
+            }
+        }
+    }
+
+    fn render_jobs<'scope>(
+        &'scope self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        world: &'scope dyn Hittable,
+        jobs: &'scope [TileJob],
+        queue: &'scope TileQueue,
+        sender: mpsc::Sender<(usize, TileResult)>
+    ) {
+        // Render tiles from the queue on local threads, which each take the next tile as they
+        // finish their last one and send it back as soon as it's done.
+        for _ in 0..self.thread_count().min(jobs.len()) {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                let mut sampler = self.new_sampler();
+                let mut rays = stats::rays_traced();
+                while let Some(index) = queue.take() {
+                    let result = self.render_tile(world, sampler.as_mut(), &jobs[index]);
+                    let rays_now = stats::rays_traced();
+                    self.progress.tile_done(rays_now - rays);
+                    rays = rays_now;
+                    // The receiver only goes away once every tile is in.
+                    let _ = sender.send((index, result));
+                    queue.finish();
+                }
+                self.stats().counters.add(&stats::take());
+            });
+        }
+    }
+
+    pub fn new_sampler(&self) -> Box<dyn Sampler> {
+        self.sampler.create(self.sample_seed, self.target_samples())
+    }
+
+    pub fn render_tile(
+        &self,
+        world: &dyn Hittable,
+        sampler: &mut dyn Sampler,
+        job: &TileJob
+    ) -> TileResult {
+        // Render a tile's samples into a film covering the tile and the pixels around it that
+        // its samples splat into.
+        let tile = job.tile;
+        let bounds = splat_bounds(tile, &self.filter, self.image_width, self.image_height);
+        let mut tile_film = Film::new(
+            bounds.x1 - bounds.x0,
+            bounds.y1 - bounds.y0,
+            self.filter,
+            self.film_aovs()
+        );
+
+        let mut first_samples = job.first_samples.iter();
+        for j in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                let first_sample = *first_samples.next().unwrap_or(&-1);
+                if first_sample < 0 {
+                    continue;
+                }
+                for sample in 0..job.samples {
+                    let mut path = PathRecord::default();
+                    let (offset, colour) = self.trace_sample(
+                        world,
+                        sampler,
+                        (i, j),
+                        (first_sample + sample) as u64,
+                        &mut path
+                    );
+                    tile_film.add_sample(i - bounds.x0, j - bounds.y0, offset, colour, &path);
+                }
+            }
+        }
+        ((bounds.x0, bounds.y0), tile_film)
+    }
+
+    fn trace_sample(
+        &self,
+        world: &dyn Hittable,
+        sampler: &mut dyn Sampler,
+        (i, j): (i32, i32),
+        sample_index: u64,
+        path: &mut PathRecord
+    ) -> ((f64, f64), Colour) {
+        // Trace one sample of pixel i,j, returning its offset from the pixel centre and colour.
+        // Every sample has its own random number stream, so the image only depends on the seed
+        // and not on the order the samples were rendered in.
+        let pixel_index = (j * self.image_width + i) as u64;
+        start_sample_stream(self.sample_seed, pixel_index, sample_index);
+        sampler.start_pixel_sample(pixel_index, sample_index);
+        let u = sampler.get_2d();
+        let offset = (u.0 - 0.5, u.1 - 0.5);
+        let r = self.get_ray(i, j, offset);
+        let colour = self.ray_colour(&r, self.max_depth, world, sampler, path);
+        (offset, colour)
+    }
+
+    fn debug_trace(&self, world: &dyn Hittable, i: i32, j: i32) {
+        // Trace the samples pixel i,j would get in a render with the same seed, printing every
+        // bounce of every path.
+        println!("Tracing pixel {},{} with seed {}", i, j, self.sample_seed);
+        let mut sampler = self.sampler.create(self.sample_seed, self.target_samples());
+        let mut sum = Colour::default();
+        for sample_index in 0..self.target_samples() {
+            println!("Sample {}:", sample_index);
+            let mut path = PathRecord { debug: true, ..Default::default() };
+            let (offset, colour) = self.trace_sample(
+                world,
+                sampler.as_mut(),
+                (i, j),
+                sample_index as u64,
+                &mut path
+            );
+            println!("  offset ({:.4}, {:.4}), colour {}", offset.0, offset.1, colour);
+            sum += colour;
+        }
+        println!("Mean colour {}", sum / (self.target_samples().max(1) as f64));
     }
 
     fn initialise(&mut self) {
@@ -175,45 +1036,158 @@ impl Camera {
         self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray {
-        // Get a randomly sampled camera ray for the pixel at location i,j.
+    fn get_ray(&self, i: i32, j: i32, offset: (f64, f64)) -> Ray {
+        // Get the camera ray through the point offset from the centre of the pixel at i,j.
 
         let pixel_center =
             self.pixel00_loc + (i as f64) * self.pixel_delta_u + (j as f64) * self.pixel_delta_v;
-        let pixel_sample = pixel_center + self.pixel_sample_square();
+        let pixel_sample =
+            pixel_center + offset.0 * self.pixel_delta_u + offset.1 * self.pixel_delta_v;
 
         let ray_origin = self.centre;
         let ray_direction = pixel_sample - ray_origin;
 
-        return Ray::new(ray_origin, ray_direction);
-    }
-
-    fn pixel_sample_square(&self) -> Vector3 {
-        // Returns a random point in the square surrounding a pixel at the origin.
-        let px = -0.5 + random_f64();
-        let py = -0.5 + random_f64();
-        return px * self.pixel_delta_u + py * self.pixel_delta_v;
+        Ray::new(ray_origin, ray_direction)
     }
 
-    fn ray_colour(&self, r: &Ray, depth: i32, world: &dyn Hittable) -> Colour {
+    fn ray_colour(
+        &self,
+        r: &Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        sampler: &mut dyn Sampler,
+        path: &mut PathRecord
+    ) -> Colour {
         let mut rec = HitRecord::default();
 
         // If we've exceeded the ray bounce limit, no more light is gathered.
         if depth <= 0 {
+            if path.debug {
+                println!("  bounce {}: bounce limit reached", path.scatters);
+            }
+            stats::count_path(PathEnd::BounceLimit, path.scatters);
             return Colour::default();
         }
 
-        if world.hit(r, Interval::new(0.001, INFINITY), &mut rec) {
+        let camera_ray = depth == self.max_depth;
+        stats::count_ray(camera_ray);
+
+        if world.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            if camera_ray {
+                path.depth = rec.t * dot(r.direction(), -self.w);
+                path.normal = rec.normal;
+                path.u = rec.u;
+                path.v = rec.v;
+                path.object_id = rec.object_id;
+                path.material_id = rec.material_id;
+                path.albedo = rec.mat.albedo(r, &rec);
+            }
+            if path.debug {
+                println!(
+                    "  bounce {}: object {}, material {}, t {:.4}, p {}, normal {}, front_face {}",
+                    path.scatters,
+                    rec.object_id,
+                    rec.material_id,
+                    rec.t,
+                    rec.p,
+                    rec.normal,
+                    rec.front_face
+                );
+            }
+
             let mut scattered: Ray = Ray::new(Vector3::default(), Vector3::default());
             let mut attenuation: Colour = Colour::default();
-            if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-                return attenuation * self.ray_colour(&scattered, depth - 1, world);
+            let lobe = rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered, sampler);
+            if path.debug {
+                match lobe {
+                    Some(lobe) =>
+                        println!(
+                            "    {:?} scatter, attenuation {}, direction {}",
+                            lobe,
+                            attenuation,
+                            unit_vector(scattered.direction())
+                        ),
+                    None => println!("    absorbed"),
+                }
             }
+            if let Some(lobe) = lobe {
+                if camera_ray {
+                    path.first_lobe = Some(lobe);
+                }
+                path.scatters += 1;
+
+                // Steps of a random walk inside an object have their own limit, as dense media
+                // take many more of them than max_depth allows bounces.
+                let mut next_depth = depth - 1;
+                let walk_limit = if rec.front_face { None } else { rec.mat.max_walk_steps() };
+                if let (Some(limit), false) = (walk_limit, camera_ray) {
+                    if path.walk_steps >= limit {
+                        if path.debug {
+                            println!("  bounce {}: walk step limit reached", path.scatters);
+                        }
+                        stats::count_path(PathEnd::BounceLimit, path.scatters);
+                        return Colour::default();
+                    }
+                    path.walk_steps += 1;
+                    next_depth = depth;
+                }
+                return attenuation * self.ray_colour(&scattered, next_depth, world, sampler, path);
+            }
+            stats::count_path(PathEnd::Absorbed, path.scatters);
             return Colour::default();
         }
 
         let unit_direction = unit_vector(r.direction());
         let a = 0.5 * (unit_direction.y() + 1.0);
-        return (self.brightness - a) * Colour::new(1.0, 1.0, 1.0) + a * Colour::new(0.5, 0.7, 1.0);
+        let sky = (self.brightness - a) * Colour::new(1.0, 1.0, 1.0) + a * Colour::new(0.5, 0.7, 1.0);
+        if camera_ray {
+            path.albedo = sky;
+        }
+        if path.debug {
+            println!("  bounce {}: sky {}", path.scatters, sky);
+        }
+        stats::count_path(PathEnd::Sky, path.scatters);
+        sky
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_matches_an_empty_camera_section() {
+        let new = Camera::new();
+        let parsed = Camera::from_yaml_str("", "camera: {}").unwrap();
+        assert_eq!(new.to_yaml(), parsed.to_yaml());
+        assert!(new.max_samples >= new.min_samples);
+    }
+
+    #[test]
+    fn subsurface_walks_dont_use_up_bounces() {
+        // A white medium absorbs nothing, so every path that gets out brings back the sky, whose
+        // blue is the camera's brightness in every direction. Bounces alone would cut nearly
+        // every walk off, so all of the light must come from the walk's own step limit.
+        let scene = crate::Scene::from_yaml_str(
+            "subsurface.yaml",
+            "
+camera:
+  image_width: 4
+  aspect_ratio: 1.0
+  samples_per_pixel: 64
+  max_depth: 3
+  vfov: 20.0
+  lookfrom: [0.0, 0.0, 3.0]
+  lookat: [0.0, 0.0, 0.0]
+materials:
+  - milk: { type: Subsurface, colour: [1.0, 1.0, 1.0], mean_free_path: [0.2, 0.2, 0.2] }
+objects:
+  - { type: Sphere, centre: [0.0, 0.0, 0.0], radius: 1.0, mat: milk }
+"
+        ).unwrap();
+        let settings = crate::RenderSettings { seed: Some(1), ..Default::default() };
+        let framebuffer = crate::render(&scene, &settings).unwrap();
+        let blue = framebuffer.pixels.iter().map(|pixel| pixel.z()).sum::<f64>() / 16.0;
+        assert!(blue > 0.98, "{}", blue);
     }
 }
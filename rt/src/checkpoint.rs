@@ -0,0 +1,228 @@
+use std::fs;
+
+use crate::{ film::Film, filter::Filter, vector3::Vector3 };
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 4;
+// Nine f64 sums and the i32 sample count.
+const PIXEL_RECORD_SIZE: usize = 9 * 8 + 4;
+
+// Everything besides the accumulated samples needed to continue an interrupted progressive
+// render: which scene it belongs to, how many passes have been rendered and the seed of the
+// random number streams. The film is saved alongside it in the same file.
+pub struct Checkpoint {
+    pub scene_path: String,
+    pub scene_hash: u64,
+    pub passes: i32,
+    pub seed: u64,
+}
+
+pub fn hash_scene(content: &str) -> u64 {
+    // 64-bit FNV-1a, which unlike std's hasher is stable across Rust versions and platforms.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &str, film: &Film) -> std::io::Result<()> {
+        let mut out: Vec<u8> = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.scene_path.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.scene_path.as_bytes());
+        out.extend_from_slice(&self.scene_hash.to_le_bytes());
+        out.extend_from_slice(&self.passes.to_le_bytes());
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&film.width.to_le_bytes());
+        out.extend_from_slice(&film.height.to_le_bytes());
+
+        for pixel in film.pixels() {
+            for value in [
+                pixel.sum.x(),
+                pixel.sum.y(),
+                pixel.sum.z(),
+                pixel.sum_luminance,
+                pixel.sum_luminance_sq,
+                pixel.weighted_sum.x(),
+                pixel.weighted_sum.y(),
+                pixel.weighted_sum.z(),
+                pixel.weight_sum,
+            ] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&pixel.samples.to_le_bytes());
+        }
+
+        out.extend_from_slice(&(film.aov_data.len() as u64).to_le_bytes());
+        for value in &film.aov_data {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // Write to a temporary file first so a crash mid-write never destroys the last good
+        // checkpoint.
+        let temporary_path = format!("{}.tmp", path);
+        fs::write(&temporary_path, out)?;
+        fs::rename(temporary_path, path)
+    }
+
+    pub fn load(path: &str) -> Result<(Self, Film), Box<dyn std::error::Error>> {
+        let bytes = fs::read(path)?;
+        let mut reader = Reader { bytes: &bytes, path };
+
+        if reader.take(4)? != MAGIC {
+            return Err(format!("'{}' is not a checkpoint file", path).into());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("'{}' has unsupported checkpoint version {}", path, version).into());
+        }
+
+        let path_length = reader.u32()? as usize;
+        let scene_path = String::from_utf8(reader.take(path_length)?.to_vec())?;
+        let scene_hash = reader.u64()?;
+        let passes = reader.i32()?;
+        let seed = reader.u64()?;
+        let width = reader.i32()?;
+        let height = reader.i32()?;
+        if width <= 0 || height <= 0 {
+            return Err(format!("'{}' has an invalid image size {}x{}", path, width, height).into());
+        }
+        // Check the size against the file before allocating a film for it.
+        let pixel_bytes = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(PIXEL_RECORD_SIZE));
+        if pixel_bytes.is_none_or(|bytes| bytes > reader.bytes.len()) {
+            return Err(format!("'{}' is truncated", path).into());
+        }
+
+        // The filter and the list of AOVs aren't saved, as they come from the scene along with
+        // the rest of the camera.
+        let mut film = Film::new(width, height, Filter::default(), Vec::new());
+        for pixel in film.pixels_mut() {
+            pixel.sum = Vector3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+            pixel.sum_luminance = reader.f64()?;
+            pixel.sum_luminance_sq = reader.f64()?;
+            pixel.weighted_sum = Vector3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+            pixel.weight_sum = reader.f64()?;
+            pixel.samples = reader.i32()?;
+        }
+
+        let aov_values = reader.u64()? as usize;
+        if aov_values.checked_mul(8).is_none_or(|bytes| bytes > reader.bytes.len()) {
+            return Err(format!("'{}' is truncated", path).into());
+        }
+        film.aov_data = (0..aov_values).map(|_| reader.f64()).collect::<Result<_, _>>()?;
+
+        Ok((Checkpoint { scene_path, scene_hash, passes, seed }, film))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    path: &'a str,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < count {
+            return Err(format!("'{}' is truncated", self.path));
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rt-{}-{}.rtc", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn round_trips_a_film() {
+        let mut film = Film::new(3, 2, Filter::default(), Vec::new());
+        for (index, pixel) in film.pixels_mut().iter_mut().enumerate() {
+            let value = index as f64;
+            pixel.sum = Vector3::new(value, value + 0.25, value + 0.5);
+            pixel.sum_luminance = value * 2.0;
+            pixel.sum_luminance_sq = value * 3.0;
+            pixel.weighted_sum = Vector3::new(-value, 1.0, value / 7.0);
+            pixel.weight_sum = value + 1.0;
+            pixel.samples = index as i32 + 4;
+        }
+        film.aov_data = vec![1.5, -2.0, 0.125];
+        let checkpoint = Checkpoint {
+            scene_path: "scene.yaml".to_string(),
+            scene_hash: hash_scene("camera: {}"),
+            passes: 12,
+            seed: 99,
+        };
+
+        let path = temporary_path("round-trip");
+        checkpoint.save(&path, &film).unwrap();
+        let (loaded, loaded_film) = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.scene_path, checkpoint.scene_path);
+        assert_eq!(loaded.scene_hash, checkpoint.scene_hash);
+        assert_eq!(loaded.passes, checkpoint.passes);
+        assert_eq!(loaded.seed, checkpoint.seed);
+        assert_eq!((loaded_film.width, loaded_film.height), (3, 2));
+        assert_eq!(loaded_film.aov_data, film.aov_data);
+        for (a, b) in film.pixels().iter().zip(loaded_film.pixels()) {
+            assert_eq!(a.sum, b.sum);
+            assert_eq!(a.sum_luminance, b.sum_luminance);
+            assert_eq!(a.sum_luminance_sq, b.sum_luminance_sq);
+            assert_eq!(a.weighted_sum, b.weighted_sum);
+            assert_eq!(a.weight_sum, b.weight_sum);
+            assert_eq!(a.samples, b.samples);
+        }
+    }
+
+    fn header(width: i32, height: i32) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&0i32.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&width.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn rejects_bad_image_sizes() {
+        let path = temporary_path("bad-size");
+        for (width, height) in [(0, 4), (4, -1), (-2, -2), (i32::MAX, i32::MAX), (1000, 1000)] {
+            fs::write(&path, header(width, height)).unwrap();
+            assert!(Checkpoint::load(&path).is_err(), "{}x{}", width, height);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
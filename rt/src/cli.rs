@@ -0,0 +1,222 @@
+use rt::{ helper::parse_duration, Region, ProgressStyle, RenderSettings };
+
+pub const DEFAULT_SCENE: &str = "/Users/harrygardiner/rt-01F/rt/config.yaml";
+
+const USAGE: &str = "\
+usage: rt [scene.yaml|scene.json|scene.toml] [options]
+       rt resume <checkpoint.rtc> [options]
+       rt convert <scene> <output.yaml|output.json|output.toml>
+       rt generate <random-spheres|cornell-box|sphere-grid> [--count <count>] [--seed <number>]
+       rt worker --listen <address:port>
+
+options:
+    --time <duration>    render progressively until the time budget runs out, e.g. 90s, 10m, 1h30m
+    --samples <count>    samples per pixel, overriding the scene
+    --count <count>      number of spheres in a generated scene
+    --seed <number>      seed for the random numbers, overriding the scene
+    --threads <count>    threads to render with, overriding the scene (0 uses every core)
+    --frames <first[-last]>
+                         render only these frames of an animated scene
+    --workers <address:port,...>
+                         share the tiles between worker processes started with rt worker
+    --region <x0,y0,x1,y1>
+                         only render the pixels from x0,y0 up to but not including x1,y1
+    --crop               with --region, write only the region instead of the full image
+    --stats-json         also write the render statistics to <filename>_stats.json
+    --progress <text|json>
+                         show progress on stderr as text, or as one JSON event per line
+    --quiet              show no progress or statistics, only warnings and errors
+    --debug-pixel <x,y>  print every bounce of each sample of one pixel instead of rendering";
+
+pub enum Command {
+    Render,
+    Resume(String), // Path of the checkpoint to resume
+    Worker(String), // Address to listen for coordinators on
+    Convert(String), // Path to write the canonical scene to
+    Generate(String), // Name of the built-in scene to generate
+}
+
+// Options given on the command line, which take precedence over the scene file.
+pub struct Options {
+    pub command: Command,
+    pub scene: String,
+    pub count: Option<usize>,
+    pub frames: Option<(i32, i32)>,
+    pub debug_pixel: Option<(i32, i32)>,
+    pub settings: RenderSettings,
+}
+
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Render,
+        scene: DEFAULT_SCENE.to_string(),
+        count: None,
+        frames: None,
+        debug_pixel: None,
+        settings: RenderSettings { progress: ProgressStyle::Text, ..RenderSettings::default() },
+    };
+    let mut scene_given = false;
+
+    // Options may also be given as --name=value.
+    let args: Vec<String> = args
+        .iter()
+        .flat_map(|arg| match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => {
+                vec![name.to_string(), value.to_string()]
+            }
+            _ => vec![arg.clone()],
+        })
+        .collect();
+
+    let mut iter = args.iter().peekable();
+    if iter.peek().map(|arg| arg.as_str()) == Some("resume") {
+        iter.next();
+        let checkpoint = iter.next().ok_or(format!("resume needs a checkpoint file\n\n{}", USAGE))?;
+        options.command = Command::Resume(checkpoint.clone());
+        scene_given = true;
+    } else if iter.peek().map(|arg| arg.as_str()) == Some("worker") {
+        iter.next();
+        if iter.next().map(|arg| arg.as_str()) != Some("--listen") {
+            return Err(format!("worker needs --listen <address:port>\n\n{}", USAGE));
+        }
+        let address = iter.next().ok_or("--listen needs an address")?;
+        options.command = Command::Worker(address.clone());
+        if let Some(arg) = iter.next() {
+            return Err(format!("unexpected argument '{}'\n\n{}", arg, USAGE));
+        }
+    } else if iter.peek().map(|arg| arg.as_str()) == Some("convert") {
+        iter.next();
+        let (Some(scene), Some(output)) = (iter.next(), iter.next()) else {
+            return Err(format!("convert needs a scene and an output file\n\n{}", USAGE));
+        };
+        options.scene = scene.clone();
+        options.command = Command::Convert(output.clone());
+        if let Some(arg) = iter.next() {
+            return Err(format!("unexpected argument '{}'\n\n{}", arg, USAGE));
+        }
+    } else if iter.peek().map(|arg| arg.as_str()) == Some("generate") {
+        iter.next();
+        let generator = iter.next().ok_or(format!("generate needs a scene name\n\n{}", USAGE))?;
+        options.command = Command::Generate(generator.clone());
+        scene_given = true;
+    }
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--time" => {
+                let value = iter.next().ok_or("--time needs a duration")?;
+                options.settings.time_limit = Some(parse_duration(value)?);
+            }
+            "--samples" => {
+                let value = iter.next().ok_or("--samples needs a count")?;
+                let samples = value.parse().map_err(|_| format!("invalid sample count '{}'", value))?;
+                options.settings.samples = Some(samples);
+            }
+            "--count" => {
+                let value = iter.next().ok_or("--count needs a count")?;
+                let count = value.parse().map_err(|_| format!("invalid count '{}'", value))?;
+                options.count = Some(count);
+            }
+            "--seed" => {
+                let value = iter.next().ok_or("--seed needs a number")?;
+                let seed = value.parse().map_err(|_| format!("invalid seed '{}'", value))?;
+                options.settings.seed = Some(seed);
+            }
+            "--threads" => {
+                let value = iter.next().ok_or("--threads needs a count")?;
+                let threads = value.parse().map_err(|_| format!("invalid thread count '{}'", value))?;
+                options.settings.threads = Some(threads);
+            }
+            "--workers" => {
+                let value = iter.next().ok_or("--workers needs a list of addresses")?;
+                options.settings.workers = value
+                    .split(',')
+                    .map(|address| address.trim().to_string())
+                    .filter(|address| !address.is_empty())
+                    .collect();
+            }
+            "--frames" => {
+                let value = iter.next().ok_or("--frames needs a frame or range")?;
+                let invalid = || format!("invalid frames '{}'", value);
+                let (first, last) = value.split_once('-').unwrap_or((value, value));
+                let first: i32 = first.trim().parse().map_err(|_| invalid())?;
+                let last: i32 = last.trim().parse().map_err(|_| invalid())?;
+                if last < first {
+                    return Err(invalid());
+                }
+                options.frames = Some((first, last));
+            }
+            "--region" => {
+                let value = iter.next().ok_or("--region needs x0,y0,x1,y1")?;
+                let invalid = || format!("invalid region '{}'", value);
+                let [x0, y0, x1, y1] = parse_integers(value).ok_or_else(invalid)?;
+                if x1 <= x0 || y1 <= y0 {
+                    return Err(invalid());
+                }
+                options.settings.region = Some(Region { x0, y0, x1, y1 });
+            }
+            "--crop" => {
+                options.settings.crop = true;
+            }
+            "--stats-json" => {
+                options.settings.stats_json = true;
+            }
+            "--progress" => {
+                let value = iter.next().ok_or("--progress needs text or json")?;
+                options.settings.progress = ProgressStyle::from_name(value)?;
+            }
+            "--quiet" => {
+                options.settings.progress = ProgressStyle::Quiet;
+            }
+            "--debug-pixel" => {
+                let value = iter.next().ok_or("--debug-pixel needs x,y")?;
+                let [x, y] = parse_integers(value).ok_or(format!("invalid pixel '{}'", value))?;
+                options.debug_pixel = Some((x, y));
+            }
+            "-h" | "--help" => {
+                return Err(USAGE.to_string());
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option '{}'\n\n{}", arg, USAGE));
+            }
+            _ if !scene_given => {
+                options.scene = arg.clone();
+                scene_given = true;
+            }
+            _ => {
+                return Err(format!("unexpected argument '{}'\n\n{}", arg, USAGE));
+            }
+        }
+    }
+
+    if options.settings.crop && options.settings.region.is_none() {
+        return Err("--crop needs --region".to_string());
+    }
+
+    Ok(options)
+}
+
+fn parse_integers<const N: usize>(text: &str) -> Option<[i32; N]> {
+    // Parses exactly N comma-separated integers.
+    let values: Vec<i32> = text
+        .split(',')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn crop_needs_a_region() {
+        assert_eq!(parse_args(&args("scene.yaml --crop")).err().unwrap(), "--crop needs --region");
+        let options = parse_args(&args("scene.yaml --region 0,0,4,4 --crop")).unwrap();
+        assert!(options.settings.crop);
+    }
+}
@@ -4,8 +4,13 @@ use crate::{ vector3::Vector3, interval::Interval };
 
 pub type Colour = Vector3;
 
+pub fn luminance(colour: Colour) -> f64 {
+    // Relative luminance of a linear Rec. 709 colour.
+    0.2126 * colour.x() + 0.7152 * colour.y() + 0.0722 * colour.z()
+}
+
 fn linear_to_gamma(linear_component: f64) -> f64 {
-    return linear_component.sqrt();
+    linear_component.sqrt()
 }
 
 pub fn write_colour(file: &mut File, colour: Colour, samples_per_pixel: i32) {
@@ -26,7 +31,7 @@ pub fn write_colour(file: &mut File, colour: Colour, samples_per_pixel: i32) {
 
     let intensity: Interval = Interval::new(0.0, 0.999);
 
-    file.write(
+    file.write_all(
         format!(
             "{} {} {}\n",
             256.0 * intensity.clamp(r),
@@ -35,3 +40,10 @@ pub fn write_colour(file: &mut File, colour: Colour, samples_per_pixel: i32) {
         ).as_bytes()
     ).expect("write failed");
 }
+
+pub fn to_rgb8(colour: Colour) -> [u8; 3] {
+    // The 8-bit gamma-corrected colour, as it is written to the image.
+    let intensity: Interval = Interval::new(0.0, 0.999);
+    let byte = |linear: f64| (256.0 * intensity.clamp(linear_to_gamma(linear))) as u8;
+    [byte(colour.x()), byte(colour.y()), byte(colour.z())]
+}
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{ sync::Arc, f64::consts::PI };
 
 use crate::{
     hittable::{ HitRecord, Hittable },
@@ -7,17 +7,19 @@ use crate::{
     vector3::{ dot, Point3, Vector3 },
     interval::Interval,
     aabb::Aabb,
+    stats,
 };
 pub struct Cylinder {
     center: Point3, // Center of the cylinder
     radius: f64, // Radius of the cylinder
     height: f64, // Height of the cylinder
-    m: Rc<dyn Material>, // Material of the cylinder
+    m: Arc<dyn Material>, // Material of the cylinder
     bbox: Aabb,
 }
 
 impl Hittable for Cylinder {
     fn hit(&self, r: &Ray, t: Interval, hit_record: &mut HitRecord) -> bool {
+        stats::count_primitive_test();
         let oc = r.origin() - self.center;
         let a = r.direction().length_squared();
         let half_height = self.height / 2.0;
@@ -58,16 +60,22 @@ impl Hittable for Cylinder {
         for root in roots {
             let hit_point = r.at(root);
 
-            if hit_point.y() > -half_height && hit_point.y() < half_height {
-                if root < closest_so_far {
-                    hit_occurred = true;
-                    closest_so_far = root;
-                    hit_record.t = root;
-                    hit_record.p = hit_point;
-                    let outward_normal = (hit_record.p - self.center) / self.radius;
-                    hit_record.normal = outward_normal;
-                    hit_record.mat = self.m.clone();
-                }
+            if hit_point.y() > -half_height && hit_point.y() < half_height && root < closest_so_far {
+                hit_occurred = true;
+                closest_so_far = root;
+                hit_record.t = root;
+                hit_record.p = hit_point;
+                hit_record.mat = self.m.clone();
+
+                // u runs around the axis and v runs up the side.
+                let dx = hit_point.x() - self.center.x();
+                let dz = hit_point.z() - self.center.z();
+                let outward_normal = Vector3::new(dx, 0.0, dz) / self.radius;
+                hit_record.set_face_normal(r, &outward_normal);
+                hit_record.u = (dz.atan2(dx) + PI) / (2.0 * PI);
+                hit_record.v = (hit_point.y() + half_height) / self.height;
+                hit_record.dpdu = 2.0 * PI * Vector3::new(-dz, 0.0, dx);
+                hit_record.dpdv = Vector3::new(0.0, self.height, 0.0);
             }
         }
 
@@ -80,7 +88,7 @@ impl Hittable for Cylinder {
 }
 
 impl Cylinder {
-    pub fn new(center: Point3, radius: f64, height: f64, m: Rc<dyn Material>) -> Self {
+    pub fn new(center: Point3, radius: f64, height: f64, m: Arc<dyn Material>) -> Self {
         Self {
             center,
             radius,
@@ -102,3 +110,34 @@ impl Cylinder {
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ colour::Colour, material::Lambertian };
+
+    #[test]
+    fn normals_face_the_ray() {
+        let cylinder = Cylinder::new(
+            Point3::default(),
+            1.0,
+            2.0,
+            Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)))
+        );
+        let t = Interval::new(0.001, f64::INFINITY);
+
+        // From outside, level with the top half of the side.
+        let r = Ray::new(Point3::new(-5.0, 0.5, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let mut rec = HitRecord::default();
+        assert!(cylinder.hit(&r, t, &mut rec));
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vector3::new(-1.0, 0.0, 0.0));
+
+        // From inside, the far wall is hit from behind.
+        let r = Ray::new(Point3::new(0.0, 0.5, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut rec = HitRecord::default();
+        assert!(cylinder.hit(&r, t, &mut rec));
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vector3::new(0.0, 0.0, -1.0));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{
+    aov::Aov,
+    colour::{ Colour, luminance },
+    film::Film,
+    vector3::{ Vector3, dot },
+};
+
+const ITERATIONS: i32 = 5; // Passes of the filter, each with twice the spacing of the last
+const SIGMA_LUMINANCE: f64 = 4.0; // Luminance differences allowed, in standard deviations
+const SIGMA_NORMAL: i32 = 128; // Power of the normals' cosine; higher keeps edges sharper
+const SIGMA_ALBEDO: f64 = 0.1; // Albedo differences allowed
+
+// B3 spline weights of the 5x5 kernel, by distance from its centre.
+const KERNEL: [f64; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+pub fn denoise(film: &Film) -> Vec<Colour> {
+    // An edge-avoiding à-trous wavelet filter (Dammertz et al. 2010, "Edge-Avoiding À-Trous
+    // Wavelet Transform for fast Global Illumination Filtering"), with the luminance weight
+    // scaled by each pixel's variance as in SVGF (Schied et al. 2017). Dividing the colour by
+    // the albedo first keeps texture detail out of the blur, and it is multiplied back after.
+    let albedo = film.aov_image(Aov::Albedo).expect("denoising needs the albedo AOV");
+    let normal = film.aov_image(Aov::Normal).expect("denoising needs the normal AOV");
+    let albedo: Vec<Colour> = albedo
+        .iter()
+        .map(|a| Colour::new(a[0], a[1], a[2]))
+        .collect();
+    let normal: Vec<Vector3> = normal
+        .iter()
+        .map(|n| Vector3::new(n[0], n[1], n[2]))
+        .collect();
+
+    let mut colour: Vec<Colour> = film
+        .pixels()
+        .iter()
+        .zip(&albedo)
+        .map(|(pixel, a)| pixel.colour() / divisor(*a))
+        .collect();
+    let mut variance: Vec<f64> = film
+        .pixels()
+        .iter()
+        .zip(&albedo)
+        .map(|(pixel, a)| pixel.variance() / luminance(divisor(*a)).powi(2))
+        .collect();
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        (colour, variance) = filter_pass(film, &colour, &variance, &albedo, &normal, step);
+    }
+
+    colour
+        .iter()
+        .zip(&albedo)
+        .map(|(c, a)| *c * divisor(*a))
+        .collect()
+}
+
+fn divisor(albedo: Colour) -> Colour {
+    // The albedo to divide the colour by, leaving black channels alone.
+    let channel = |a: f64| if a > 1e-3 { a } else { 1.0 };
+    Colour::new(channel(albedo.x()), channel(albedo.y()), channel(albedo.z()))
+}
+
+fn filter_pass(
+    film: &Film,
+    colour: &[Colour],
+    variance: &[f64],
+    albedo: &[Colour],
+    normal: &[Vector3],
+    step: i32
+) -> (Vec<Colour>, Vec<f64>) {
+    let (width, height) = (film.width, film.height);
+    let mut filtered_colour = vec![Colour::default(); colour.len()];
+    let mut filtered_variance = vec![0.0; variance.len()];
+
+    for j in 0..height {
+        for i in 0..width {
+            let p = (j * width + i) as usize;
+            let luminance_p = luminance(colour[p]);
+            let luminance_scale = SIGMA_LUMINANCE * variance[p].sqrt() + 1e-6;
+
+            let mut colour_sum = Colour::default();
+            let mut variance_sum = 0.0;
+            let mut weight_sum = 0.0;
+            for dy in -2..=2i32 {
+                for dx in -2..=2i32 {
+                    let (qi, qj) = (i + dx * step, j + dy * step);
+                    if qi < 0 || qi >= width || qj < 0 || qj >= height {
+                        continue;
+                    }
+                    let q = (qj * width + qi) as usize;
+
+                    let kernel =
+                        KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize];
+                    let luminance_weight = if luminance_scale.is_finite() {
+                        (-(luminance_p - luminance(colour[q])).abs() / luminance_scale).exp()
+                    } else {
+                        1.0
+                    };
+                    // Camera rays that missed have no normal, and only match each other.
+                    let normal_weight = if normal[p].near_zero() && normal[q].near_zero() {
+                        1.0
+                    } else {
+                        dot(normal[p], normal[q]).max(0.0).powi(SIGMA_NORMAL)
+                    };
+                    let albedo_difference = albedo[p] - albedo[q];
+                    let albedo_weight = (
+                        -dot(albedo_difference, albedo_difference) /
+                        (SIGMA_ALBEDO * SIGMA_ALBEDO)
+                    ).exp();
+
+                    let weight = kernel * luminance_weight * normal_weight * albedo_weight;
+                    colour_sum += weight * colour[q];
+                    if variance[q].is_finite() {
+                        variance_sum += weight * weight * variance[q];
+                    }
+                    weight_sum += weight;
+                }
+            }
+
+            if weight_sum > 1e-12 {
+                filtered_colour[p] = colour_sum / weight_sum;
+                filtered_variance[p] = if variance[p].is_finite() {
+                    variance_sum / (weight_sum * weight_sum)
+                } else {
+                    f64::INFINITY
+                };
+            } else {
+                filtered_colour[p] = colour[p];
+                filtered_variance[p] = variance[p];
+            }
+        }
+    }
+
+    (filtered_colour, filtered_variance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ aov::PathRecord, filter::Filter };
+
+    const WIDTH: i32 = 12;
+
+    fn film_with(column: impl Fn(i32) -> (f64, Colour, Vector3)) -> Film {
+        // A film whose columns each have a brightness, an albedo and a normal. Every pixel gets
+        // a dark and a bright sample around its brightness, so the image looks noisy.
+        let mut film = Film::new(WIDTH, 6, Filter::default(), vec![Aov::Albedo, Aov::Normal]);
+        for j in 0..6 {
+            for i in 0..WIDTH {
+                let (brightness, albedo, normal) = column(i);
+                let path = PathRecord { albedo, normal, ..PathRecord::default() };
+                for scale in [0.5, 1.5] {
+                    let colour = Colour::new(1.0, 1.0, 1.0) * (brightness * scale);
+                    film.add_sample(i, j, (0.0, 0.0), colour, &path);
+                }
+            }
+        }
+        film
+    }
+
+    fn largest_change(film: &Film) -> f64 {
+        // How far the denoiser moved the pixel furthest from where it was.
+        denoise(film)
+            .iter()
+            .zip(film.pixels())
+            .map(|(denoised, pixel)| (*denoised - pixel.colour()).length())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn constant_images_pass_through() {
+        let grey = Colour::new(0.5, 0.5, 0.5);
+        let film = film_with(|_| (0.4, grey, Vector3::new(0.0, 0.0, 1.0)));
+        assert!(largest_change(&film) < 1e-9);
+    }
+
+    #[test]
+    fn normals_and_albedo_keep_edges_hard() {
+        // Bright on the left half and dark on the right, split by a crease in the normals, by
+        // a change of albedo, or by nothing but the brightness.
+        let (grey, up, side) = (
+            Colour::new(0.5, 0.5, 0.5),
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(1.0, 0.0, 0.0),
+        );
+        let crease = film_with(|i| if i < WIDTH / 2 { (0.8, grey, up) } else { (0.2, grey, side) });
+        assert!(largest_change(&crease) < 1e-6);
+
+        let (light, dark) = (Colour::new(0.8, 0.8, 0.8), Colour::new(0.2, 0.2, 0.2));
+        let paint = film_with(|i| if i < WIDTH / 2 { (0.8, light, up) } else { (0.05, dark, up) });
+        assert!(largest_change(&paint) < 1e-6);
+
+        let plain = film_with(|i| if i < WIDTH / 2 { (0.8, grey, up) } else { (0.2, grey, up) });
+        assert!(largest_change(&plain) > 0.05);
+    }
+}
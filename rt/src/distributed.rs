@@ -0,0 +1,763 @@
+use std::{
+    io::{ self, BufReader, BufWriter, Read, Write },
+    net::{ TcpListener, TcpStream, ToSocketAddrs },
+    sync::{ mpsc, Mutex },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    aov::Aov,
+    film::{ Film, Region },
+    filter::Filter,
+    progress::Progress,
+    registry::{ material_constructor, object_constructor },
+    tile::{ splat_bounds, TileJob, TileQueue, TileResult },
+    vector3::Vector3,
+};
+
+// The coordinator connects to each worker and sends it a Scene message. Once the worker has
+// loaded the scene it answers Ready with how many tiles it renders at once, and the coordinator
+// keeps that many Jobs with it. The worker renders them on a thread each and answers each with
+// a Result holding the film the tile was rendered into, tagged with the job's id, in whatever
+// order they finish. Numbers are little-endian and strings are a u64 length followed by UTF-8
+// bytes.
+const MAGIC: &[u8; 4] = b"RTWK";
+const VERSION: u32 = 3;
+
+const SCENE: u8 = 1;
+const READY: u8 = 2;
+const JOB: u8 = 3;
+const RESULT: u8 = 4;
+
+// How long to wait on a worker before counting it as dropped out: a fixed allowance for
+// connecting, loading the scene or sending a message, and for a tile, a generous allowance per
+// sample on top so a slow tile isn't mistaken for a stalled worker.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+const SCENE_TIMEOUT: Duration = Duration::from_secs(120);
+const SAMPLE_TIMEOUT: Duration = Duration::from_micros(500);
+
+// Most tiles a worker may ask to be given at once.
+const MAX_WORKER_THREADS: u32 = 1024;
+
+// What a worker needs to render the same image as the coordinator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneSetup {
+    pub scene_path: String, // Used to find files the scene refers to
+    pub scene: String, // Contents of the scene file
+    pub seed: u64,
+    pub samples_per_pixel: i32,
+    pub max_samples: i32,
+    pub material_types: Vec<String>, // Registered types the scene uses, which the worker needs
+    pub object_types: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    threads: usize, // Tiles the worker renders at once
+}
+
+#[derive(Debug)]
+struct Worker {
+    address: String,
+    connection: Mutex<Option<Connection>>, // None once the worker has dropped out
+}
+
+// The workers a render shares its tiles with.
+#[derive(Debug)]
+pub struct Cluster {
+    workers: Vec<Worker>,
+}
+
+impl Cluster {
+    pub fn connect(addresses: &[String], setup: &SceneSetup, progress: &Progress) -> Cluster {
+        // Workers that can't be reached or can't load the scene are left out.
+        let mut workers = Vec::new();
+        for address in addresses {
+            match connect_worker(address, setup) {
+                Ok(connection) => {
+                    progress.message(
+                        &format!(
+                            "Connected to worker {} with {} threads",
+                            address,
+                            connection.threads
+                        )
+                    );
+                    workers.push(Worker {
+                        address: address.clone(),
+                        connection: Mutex::new(Some(connection)),
+                    });
+                }
+                Err(err) => {
+                    progress.warning(&format!("Worker {} is unavailable: {}", address, err));
+                }
+            }
+        }
+        Cluster { workers }
+    }
+
+    pub fn render<'scope>(
+        &'scope self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        jobs: &'scope [TileJob],
+        queue: &'scope TileQueue,
+        (filter, aovs, image_size): (Filter, &'scope [Aov], (i32, i32)),
+        progress: &'scope Progress,
+        results: &mpsc::Sender<(usize, TileResult)>
+    ) {
+        // Start a thread per worker that takes tiles from the queue alongside the local threads
+        // and sends the worker's films back to results. A worker that drops out gives the tiles
+        // it had back to the queue for the others.
+        for worker in &self.workers {
+            let results = results.clone();
+            scope.spawn(move || {
+                let mut connection = worker.connection.lock().unwrap();
+                let Some(live) = connection.as_mut() else {
+                    return;
+                };
+                let mut in_flight = Vec::new();
+                let outcome = render_remote(
+                    live,
+                    &mut in_flight,
+                    jobs,
+                    queue,
+                    (filter, aovs, image_size),
+                    progress,
+                    &results
+                );
+                if let Err(err) = outcome {
+                    progress.warning(&format!("Worker {} dropped out: {}", worker.address, err));
+                    for index in in_flight {
+                        queue.give_back(index);
+                    }
+                    *connection = None;
+                }
+            });
+        }
+    }
+}
+
+fn connect_worker(address: &str, setup: &SceneSetup) -> io::Result<Connection> {
+    let mut last_error = invalid("address doesn't resolve");
+    let mut stream = None;
+    for socket_address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_address, NETWORK_TIMEOUT) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(err) => {
+                last_error = err;
+            }
+        }
+    }
+    let mut stream = stream.ok_or(last_error)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.set_read_timeout(Some(SCENE_TIMEOUT))?;
+    stream.write_all(&encode_scene(setup))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let threads = read_ready(&mut reader)?;
+    Ok(Connection { reader, writer: stream, threads: threads as usize })
+}
+
+fn render_remote(
+    connection: &mut Connection,
+    in_flight: &mut Vec<usize>,
+    jobs: &[TileJob],
+    queue: &TileQueue,
+    (filter, aovs, (width, height)): (Filter, &[Aov], (i32, i32)),
+    progress: &Progress,
+    results: &mpsc::Sender<(usize, TileResult)>
+) -> io::Result<()> {
+    // Keep as many tiles with the worker as it renders at once, so its threads never wait on
+    // the network, until the queue runs out. in_flight holds the tiles the worker has.
+    loop {
+        while in_flight.len() < connection.threads {
+            // Only wait for tiles to be given back when the worker has nothing else to do.
+            let next = if in_flight.is_empty() { queue.take() } else { queue.try_take() };
+            let Some(index) = next else {
+                break;
+            };
+            in_flight.push(index);
+            connection.writer.write_all(&encode_job(index as u32, &jobs[index]))?;
+        }
+        if in_flight.is_empty() {
+            return Ok(());
+        }
+
+        // A worker that stops answering, e.g. because it was suspended, times out like one
+        // that disconnected. Any of the tiles it has could be the next to come back.
+        let timeout = in_flight
+            .iter()
+            .map(|index| job_timeout(&jobs[*index]))
+            .max()
+            .unwrap_or(NETWORK_TIMEOUT);
+        connection.reader.get_ref().set_read_timeout(Some(timeout))?;
+        let (id, result) = read_result(&mut connection.reader, filter, aovs)?;
+        let Some(position) = in_flight.iter().position(|index| *index == (id as usize)) else {
+            return Err(invalid("result for a tile the worker wasn't given"));
+        };
+        // A film covering other pixels than the tile's would be merged into the wrong place.
+        let bounds = splat_bounds(jobs[in_flight[position]].tile, &filter, width, height);
+        let ((x0, y0), film) = &result;
+        let covered = Region { x0: *x0, y0: *y0, x1: x0 + film.width, y1: y0 + film.height };
+        if covered != bounds {
+            return Err(invalid("result doesn't cover the tile it's for"));
+        }
+        let index = in_flight.swap_remove(position);
+        // The receiver only goes away once every tile is in.
+        let _ = results.send((index, result));
+        queue.finish();
+        progress.tile_done(0);
+    }
+}
+
+fn job_timeout(job: &TileJob) -> Duration {
+    let pixels = job.first_samples.iter().filter(|first| **first >= 0).count() as u32;
+    NETWORK_TIMEOUT + SAMPLE_TIMEOUT * pixels.saturating_mul(job.samples.max(0) as u32)
+}
+
+pub fn serve(address: &str) -> io::Result<()> {
+    serve_listener(TcpListener::bind(address)?)
+}
+
+pub fn serve_listener(listener: TcpListener) -> io::Result<()> {
+    // Render tiles for any coordinator that connects, each connection on threads of its own.
+    eprintln!("Worker listening on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Connection failed: {}", err);
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|peer| peer.to_string())
+                .unwrap_or_default();
+            eprintln!("Coordinator {} connected", peer);
+            match serve_connection(stream) {
+                Ok(()) => eprintln!("Coordinator {} finished", peer),
+                Err(err) => eprintln!("Coordinator {} disconnected: {}", peer, err),
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_connection(stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let writer = Mutex::new(BufWriter::new(stream));
+
+    let setup = read_scene(&mut reader)?;
+    // A worker without the scene's registered types would render a different scene from the
+    // coordinator's, so it refuses the scene instead.
+    for name in &setup.material_types {
+        if material_constructor(name).is_none() {
+            return Err(invalid(&format!("material type '{}' isn't registered here", name)));
+        }
+    }
+    for name in &setup.object_types {
+        if object_constructor(name).is_none() {
+            return Err(invalid(&format!("object type '{}' isn't registered here", name)));
+        }
+    }
+    let (world, mut cam, _) = crate::scene::parse_scene(&setup.scene_path, &setup.scene)
+        .map_err(|err| invalid(&format!("bad scene: {}", err)))?;
+    cam.samples_per_pixel = setup.samples_per_pixel;
+    cam.max_samples = setup.max_samples;
+    cam.initialise_worker(setup.seed);
+    let threads = (cam.thread_count() as u32).clamp(1, MAX_WORKER_THREADS);
+
+    {
+        let mut writer = writer.lock().unwrap();
+        writer.write_all(&encode_ready(threads))?;
+        writer.flush()?;
+    }
+
+    // Each thread renders the next job that has come in and sends its result straight back.
+    let (job_sender, job_receiver) = mpsc::channel::<(u32, TileJob)>();
+    let job_receiver = Mutex::new(job_receiver);
+    thread::scope(|scope| {
+        let renderers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| -> io::Result<()> {
+                    let mut sampler = cam.new_sampler();
+                    loop {
+                        let next = job_receiver.lock().unwrap().recv();
+                        let Ok((id, job)) = next else {
+                            return Ok(());
+                        };
+                        let result = cam.render_tile(&world, sampler.as_mut(), &job);
+                        let message = encode_result(id, &result);
+                        let mut writer = writer.lock().unwrap();
+                        writer.write_all(&message)?;
+                        writer.flush()?;
+                    }
+                })
+            })
+            .collect();
+
+        // The coordinator closing the connection is the normal end of a render.
+        let mut outcome = Ok(());
+        loop {
+            match read_job(&mut reader, cam.image_width, cam.image_height()) {
+                Ok(Some(job)) => {
+                    if job_sender.send(job).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => {
+                    break;
+                }
+                Err(err) => {
+                    outcome = Err(err);
+                    break;
+                }
+            }
+        }
+        drop(job_sender);
+        for renderer in renderers {
+            let rendered = renderer.join().unwrap();
+            if outcome.is_ok() {
+                outcome = rendered;
+            }
+        }
+        outcome
+    })
+}
+
+fn encode_scene(setup: &SceneSetup) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&VERSION.to_le_bytes());
+    message.push(SCENE);
+    put_string(&mut message, &setup.scene_path);
+    put_string(&mut message, &setup.scene);
+    message.extend_from_slice(&setup.seed.to_le_bytes());
+    message.extend_from_slice(&setup.samples_per_pixel.to_le_bytes());
+    message.extend_from_slice(&setup.max_samples.to_le_bytes());
+    for types in [&setup.material_types, &setup.object_types] {
+        message.extend_from_slice(&(types.len() as u32).to_le_bytes());
+        for name in types {
+            put_string(&mut message, name);
+        }
+    }
+    message
+}
+
+fn read_scene(reader: &mut impl Read) -> io::Result<SceneSetup> {
+    let magic: [u8; 4] = read_bytes(reader)?;
+    if &magic != MAGIC || read_u32(reader)? != VERSION {
+        return Err(invalid("not a coordinator of this version"));
+    }
+    if read_u8(reader)? != SCENE {
+        return Err(invalid("expected a scene"));
+    }
+    let read_types = |reader: &mut _| -> io::Result<Vec<String>> {
+        let count = read_u32(reader)?;
+        if count > 1 << 16 {
+            return Err(invalid("too many registered types"));
+        }
+        (0..count).map(|_| read_string(reader)).collect()
+    };
+    Ok(SceneSetup {
+        scene_path: read_string(reader)?,
+        scene: read_string(reader)?,
+        seed: read_u64(reader)?,
+        samples_per_pixel: read_i32(reader)?,
+        max_samples: read_i32(reader)?,
+        material_types: read_types(reader)?,
+        object_types: read_types(reader)?,
+    })
+}
+
+fn encode_ready(threads: u32) -> Vec<u8> {
+    let mut message = vec![READY];
+    message.extend_from_slice(&threads.to_le_bytes());
+    message
+}
+
+fn read_ready(reader: &mut impl Read) -> io::Result<u32> {
+    if read_u8(reader)? != READY {
+        return Err(invalid("expected the worker to be ready"));
+    }
+    let threads = read_u32(reader)?;
+    if threads == 0 || threads > MAX_WORKER_THREADS {
+        return Err(invalid("bad thread count"));
+    }
+    Ok(threads)
+}
+
+fn encode_job(id: u32, job: &TileJob) -> Vec<u8> {
+    let mut message = vec![JOB];
+    message.extend_from_slice(&id.to_le_bytes());
+    for value in [job.tile.x0, job.tile.y0, job.tile.x1, job.tile.y1, job.samples] {
+        message.extend_from_slice(&value.to_le_bytes());
+    }
+    message.extend_from_slice(&(job.first_samples.len() as u64).to_le_bytes());
+    for first_sample in &job.first_samples {
+        message.extend_from_slice(&first_sample.to_le_bytes());
+    }
+    message
+}
+
+fn read_job(reader: &mut impl Read, width: i32, height: i32) -> io::Result<Option<(u32, TileJob)>> {
+    // Reads the next job for an image of this size, or None if the connection has closed.
+    let mut kind = [0];
+    if reader.read(&mut kind)? == 0 {
+        return Ok(None);
+    }
+    if kind[0] != JOB {
+        return Err(invalid("expected a job"));
+    }
+    let id = read_u32(reader)?;
+    let tile = Region {
+        x0: read_i32(reader)?,
+        y0: read_i32(reader)?,
+        x1: read_i32(reader)?,
+        y1: read_i32(reader)?,
+    };
+    let samples = read_i32(reader)?;
+    let count = read_u64(reader)?;
+    if tile.clamp(width, height) != Some(tile) {
+        return Err(invalid("tile is outside the image"));
+    }
+    if count != ((tile.x1 - tile.x0) * (tile.y1 - tile.y0)) as u64 {
+        return Err(invalid("tile size doesn't match its samples"));
+    }
+    let first_samples = (0..count)
+        .map(|_| read_i32(reader))
+        .collect::<io::Result<Vec<i32>>>()?;
+    Ok(Some((id, TileJob { tile, samples, first_samples })))
+}
+
+fn encode_result(id: u32, ((x0, y0), film): &TileResult) -> Vec<u8> {
+    let mut message = vec![RESULT];
+    message.extend_from_slice(&id.to_le_bytes());
+    for value in [*x0, *y0, film.width, film.height] {
+        message.extend_from_slice(&value.to_le_bytes());
+    }
+    for pixel in film.pixels() {
+        for value in [
+            pixel.sum.x(),
+            pixel.sum.y(),
+            pixel.sum.z(),
+            pixel.sum_luminance,
+            pixel.sum_luminance_sq,
+            pixel.weighted_sum.x(),
+            pixel.weighted_sum.y(),
+            pixel.weighted_sum.z(),
+            pixel.weight_sum,
+        ] {
+            message.extend_from_slice(&value.to_le_bytes());
+        }
+        message.extend_from_slice(&pixel.samples.to_le_bytes());
+    }
+    message.extend_from_slice(&(film.aov_data.len() as u64).to_le_bytes());
+    for value in &film.aov_data {
+        message.extend_from_slice(&value.to_le_bytes());
+    }
+    message
+}
+
+fn read_result(
+    reader: &mut impl Read,
+    filter: Filter,
+    aovs: &[Aov]
+) -> io::Result<(u32, TileResult)> {
+    if read_u8(reader)? != RESULT {
+        return Err(invalid("expected a tile"));
+    }
+    let id = read_u32(reader)?;
+    let x0 = read_i32(reader)?;
+    let y0 = read_i32(reader)?;
+    let width = read_i32(reader)?;
+    let height = read_i32(reader)?;
+    if width <= 0 || height <= 0 || width > 1 << 16 || height > 1 << 16 {
+        return Err(invalid("bad tile size"));
+    }
+
+    let mut film = Film::new(width, height, filter, aovs.to_vec());
+    for pixel in film.pixels_mut() {
+        pixel.sum = read_vector(reader)?;
+        pixel.sum_luminance = read_f64(reader)?;
+        pixel.sum_luminance_sq = read_f64(reader)?;
+        pixel.weighted_sum = read_vector(reader)?;
+        pixel.weight_sum = read_f64(reader)?;
+        pixel.samples = read_i32(reader)?;
+    }
+    if read_u64(reader)? != (film.aov_data.len() as u64) {
+        return Err(invalid("AOVs don't match"));
+    }
+    for value in film.aov_data.iter_mut() {
+        *value = read_f64(reader)?;
+    }
+    Ok((id, ((x0, y0), film)))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn put_string(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&(text.len() as u64).to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).map_err(|err| {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")
+            }
+            // Read timeouts show up as WouldBlock on Unix.
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                io::Error::new(io::ErrorKind::TimedOut, "timed out")
+            }
+            _ => err,
+        }
+    })?;
+    Ok(bytes)
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    Ok(read_bytes::<1>(reader)?[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    Ok(i32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_vector(reader: &mut impl Read) -> io::Result<Vector3> {
+    Ok(Vector3::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?))
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_u64(reader)?;
+    if length > 1 << 30 {
+        return Err(invalid("string too long"));
+    }
+    let mut bytes = vec![0; length as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid("string is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> TileJob {
+        TileJob {
+            tile: Region { x0: 4, y0: 2, x1: 6, y1: 5 },
+            samples: 8,
+            first_samples: vec![0, 8, -1, 16, 24, -1],
+        }
+    }
+
+    #[test]
+    fn scene_round_trips() {
+        let setup = SceneSetup {
+            scene_path: "scenes/test.yaml".to_string(),
+            scene: "camera: {}\nobjects: []\n".to_string(),
+            seed: 1234,
+            samples_per_pixel: 64,
+            max_samples: 256,
+            material_types: vec!["Chalk".to_string()],
+            object_types: vec!["Torus".to_string(), "Knot".to_string()],
+        };
+        assert_eq!(read_scene(&mut encode_scene(&setup).as_slice()).unwrap(), setup);
+    }
+
+    #[test]
+    fn ready_round_trips() {
+        assert_eq!(read_ready(&mut encode_ready(12).as_slice()).unwrap(), 12);
+        assert!(read_ready(&mut encode_ready(0).as_slice()).is_err());
+        assert!(read_ready(&mut encode_ready(MAX_WORKER_THREADS + 1).as_slice()).is_err());
+    }
+
+    #[test]
+    fn job_round_trips() {
+        let (id, decoded) = read_job(&mut encode_job(7, &job()).as_slice(), 10, 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, 7);
+        assert_eq!(decoded.tile, job().tile);
+        assert_eq!(decoded.samples, job().samples);
+        assert_eq!(decoded.first_samples, job().first_samples);
+    }
+
+    #[test]
+    fn result_round_trips() {
+        let aovs = [Aov::Depth];
+        let mut film = Film::new(2, 3, Filter::default(), aovs.to_vec());
+        for (index, pixel) in film.pixels_mut().iter_mut().enumerate() {
+            let value = index as f64;
+            pixel.sum = Vector3::new(value, value * 0.5, -value);
+            pixel.sum_luminance = value + 0.125;
+            pixel.sum_luminance_sq = value * value;
+            pixel.weighted_sum = Vector3::new(1.0, value, 2.0);
+            pixel.weight_sum = value * 0.25;
+            pixel.samples = index as i32;
+        }
+        film.aov_data = (0..film.aov_data.len()).map(|index| index as f64 * 1.5).collect();
+
+        let result = ((4, 2), film);
+        let message = encode_result(3, &result);
+        let film = result.1;
+        let (id, ((x0, y0), decoded)) = read_result(
+            &mut message.as_slice(),
+            Filter::default(),
+            &aovs
+        ).unwrap();
+        assert_eq!((id, x0, y0), (3, 4, 2));
+        assert_eq!((decoded.width, decoded.height), (2, 3));
+        assert_eq!(decoded.aov_data, film.aov_data);
+        for (a, b) in film.pixels().iter().zip(decoded.pixels()) {
+            assert_eq!(a.sum, b.sum);
+            assert_eq!(a.sum_luminance, b.sum_luminance);
+            assert_eq!(a.sum_luminance_sq, b.sum_luminance_sq);
+            assert_eq!(a.weighted_sum, b.weighted_sum);
+            assert_eq!(a.weight_sum, b.weight_sum);
+            assert_eq!(a.samples, b.samples);
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut message = encode_scene(&SceneSetup {
+            scene_path: String::new(),
+            scene: String::new(),
+            seed: 0,
+            samples_per_pixel: 1,
+            max_samples: 1,
+            material_types: Vec::new(),
+            object_types: Vec::new(),
+        });
+        message[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(read_scene(&mut message.as_slice()).is_err());
+        message[0] = b'X';
+        assert!(read_scene(&mut message.as_slice()).is_err());
+    }
+
+    #[test]
+    fn workers_refuse_types_they_dont_know() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut coordinator = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        coordinator
+            .write_all(&encode_scene(&SceneSetup {
+                scene_path: String::new(),
+                scene: "camera: {}\nobjects:\n  - { type: NoSuchObject }\n".to_string(),
+                seed: 0,
+                samples_per_pixel: 1,
+                max_samples: 1,
+                material_types: Vec::new(),
+                object_types: vec!["NoSuchObject".to_string()],
+            }))
+            .unwrap();
+        let err = serve_connection(stream).unwrap_err();
+        assert_eq!(err.to_string(), "object type 'NoSuchObject' isn't registered here");
+    }
+
+    #[test]
+    fn rejects_bad_jobs() {
+        // Outside an image of 5 by 5 pixels.
+        assert!(read_job(&mut encode_job(0, &job()).as_slice(), 5, 5).is_err());
+
+        let mut short = job();
+        short.first_samples.pop();
+        assert!(read_job(&mut encode_job(0, &short).as_slice(), 10, 10).is_err());
+
+        let message = encode_job(0, &job());
+        assert!(read_job(&mut &message[..message.len() - 1], 10, 10).is_err());
+        assert!(read_job(&mut &[RESULT][..], 10, 10).is_err());
+        assert!(read_job(&mut &[][..], 10, 10).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_bad_results() {
+        let film = Film::new(2, 2, Filter::default(), Vec::new());
+        let message = encode_result(0, &((0, 0), film));
+        // Truncated, or with AOVs the coordinator didn't ask for.
+        assert!(read_result(&mut &message[..message.len() - 1], Filter::default(), &[]).is_err());
+        assert!(read_result(&mut message.as_slice(), Filter::default(), &[Aov::Depth]).is_err());
+
+        let mut message = message.clone();
+        message[13..17].copy_from_slice(&0i32.to_le_bytes());
+        assert!(read_result(&mut message.as_slice(), Filter::default(), &[]).is_err());
+    }
+
+    #[test]
+    fn results_must_cover_their_tiles() {
+        // A worker that answers its first tile properly and its second one a pixel out.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let worker = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            for offset in [0, 1] {
+                let (id, _) = read_job(&mut reader, 10, 10).unwrap().unwrap();
+                let film = Film::new(4, 5, Filter::default(), Vec::new());
+                writer.write_all(&encode_result(id, &((3 + offset, 1), film))).unwrap();
+            }
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            threads: 1,
+        };
+        let jobs = [job(), job()];
+        let queue = TileQueue::new(0..jobs.len());
+        let (sender, receiver) = mpsc::channel();
+        let mut in_flight = Vec::new();
+        let outcome = render_remote(
+            &mut connection,
+            &mut in_flight,
+            &jobs,
+            &queue,
+            (Filter::default(), &[], (10, 10)),
+            &Progress::default(),
+            &sender
+        );
+        worker.join().unwrap();
+
+        assert!(outcome.is_err());
+        assert_eq!(in_flight, [1]);
+        let (index, ((x0, y0), _)) = receiver.try_recv().unwrap();
+        assert_eq!((index, x0, y0), (0, 3, 1));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn job_timeout_grows_with_the_tile() {
+        let mut small = job();
+        small.samples = 1;
+        assert!(job_timeout(&small) < job_timeout(&job()));
+        assert!(job_timeout(&small) >= NETWORK_TIMEOUT);
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+
+// A channel of an OpenEXR image, named "layer.channel" or just "channel", with one value per
+// pixel in rows from the top.
+pub struct Channel {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+pub fn write_exr(path: &str, width: i32, height: i32, channels: &mut [Channel]) -> std::io::Result<()> {
+    // Writes an uncompressed scanline OpenEXR file with 32-bit float channels.
+    // Readers expect the channels in alphabetical order.
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    out.extend_from_slice(&2u32.to_le_bytes()); // Version 2, single-part scanline
+
+    let mut channel_list: Vec<u8> = Vec::new();
+    for channel in channels.iter() {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        channel_list.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    channel_list.push(0);
+
+    let mut window: Vec<u8> = Vec::new();
+    for value in [0, 0, width - 1, height - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.extend_from_slice(kind.as_bytes());
+        out.push(0);
+        out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        out.extend_from_slice(value);
+    };
+    attribute("channels", "chlist", &channel_list);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    out.push(0);
+
+    // The offset table gives the file position of every scanline, which all have the same size.
+    let line_size = (width as usize) * channels.len() * 4;
+    let first_line = out.len() + (height as usize) * 8;
+    for y in 0..height as usize {
+        out.extend_from_slice(&((first_line + y * (8 + line_size)) as u64).to_le_bytes());
+    }
+
+    for y in 0..height {
+        out.extend_from_slice(&y.to_le_bytes());
+        out.extend_from_slice(&(line_size as i32).to_le_bytes());
+        let row = (y * width) as usize..((y + 1) * width) as usize;
+        for channel in channels.iter() {
+            for value in &channel.values[row.clone()] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    fs::write(path, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aov::{ Aov, PathRecord },
+        colour::Colour,
+        film::Film,
+        filter::Filter,
+        vector3::Vector3,
+    };
+
+    fn read_exr(path: &str) -> (i32, i32, Vec<Channel>) {
+        // Reads back the files write_exr writes: the channel list and data window from the
+        // header, then every scanline through the offset table.
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(bytes[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        let text = |at: &mut usize| {
+            let end = *at + bytes[*at..].iter().position(|byte| *byte == 0).unwrap();
+            let text = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+            *at = end + 1;
+            text
+        };
+        let int = |at: usize| i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+
+        let (mut channels, mut width, mut height) = (Vec::new(), 0, 0);
+        let mut at = 8;
+        loop {
+            let name = text(&mut at);
+            if name.is_empty() {
+                break;
+            }
+            let kind = text(&mut at);
+            let size = int(at) as usize;
+            at += 4;
+            match name.as_str() {
+                "channels" => {
+                    let mut entry = at;
+                    loop {
+                        let name = text(&mut entry);
+                        if name.is_empty() {
+                            break;
+                        }
+                        assert_eq!(int(entry), 2, "{} isn't FLOAT", name);
+                        entry += 16;
+                        channels.push(Channel { name, values: Vec::new() });
+                    }
+                    assert_eq!(entry, at + size);
+                }
+                "dataWindow" => {
+                    assert_eq!(kind, "box2i");
+                    width = int(at + 8) - int(at) + 1;
+                    height = int(at + 12) - int(at + 4) + 1;
+                }
+                _ => {}
+            }
+            at += size;
+        }
+
+        for y in 0..height {
+            let table = at + (y as usize) * 8;
+            let mut line = u64::from_le_bytes(bytes[table..table + 8].try_into().unwrap()) as usize;
+            assert_eq!(int(line), y);
+            assert_eq!(int(line + 4) as usize, (width as usize) * channels.len() * 4);
+            line += 8;
+            for channel in &mut channels {
+                for _ in 0..width {
+                    let value = bytes[line..line + 4].try_into().unwrap();
+                    channel.values.push(f32::from_le_bytes(value));
+                    line += 4;
+                }
+            }
+        }
+        (width, height, channels)
+    }
+
+    #[test]
+    fn round_trips_depth_and_normal_aovs() {
+        // Two samples in each pixel of a 3 by 2 film, whose AOVs are written as their means.
+        let mut film = Film::new(3, 2, Filter::default(), vec![Aov::Depth, Aov::Normal]);
+        for index in 0..6 {
+            let (i, j) = (index % 3, index / 3);
+            for (depth, nx) in [(index as f64, 0.5), (index as f64 + 1.0, -0.5)] {
+                let path = PathRecord {
+                    depth,
+                    normal: Vector3::new(nx, 0.25 * index as f64, 1.0),
+                    ..PathRecord::default()
+                };
+                film.add_sample(i, j, (0.0, 0.0), Colour::new(1.0, 0.5, 0.25), &path);
+            }
+        }
+        let colours: Vec<Colour> = (0..6)
+            .map(|index| Colour::new(index as f64, 0.0, 1.0))
+            .collect();
+        let path = std::env::temp_dir().join(format!("rt-aovs-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        film.write_exr(path, &colours, &[Aov::Depth, Aov::Normal]).unwrap();
+        let (width, height, channels) = read_exr(&(path.to_string() + ".exr"));
+        fs::remove_file(path.to_string() + ".exr").unwrap();
+
+        assert_eq!((width, height), (3, 2));
+        let names: Vec<&str> = channels.iter().map(|channel| channel.name.as_str()).collect();
+        assert_eq!(names, ["B", "G", "R", "depth.Z", "normal.X", "normal.Y", "normal.Z"]);
+        for index in 0..6 {
+            let value = index as f64;
+            let expected = [1.0, 0.0, value, value + 0.5, 0.0, 0.25 * value, 1.0];
+            for (channel, expected) in channels.iter().zip(expected) {
+                let message = format!("{} of pixel {}", channel.name, index);
+                assert_eq!(channel.values[index], expected as f32, "{}", message);
+            }
+        }
+    }
+}
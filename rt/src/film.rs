@@ -0,0 +1,481 @@
+use std::{ fs::File, io::Write };
+
+use serde::Deserialize;
+
+use crate::{
+    aov::{ Aov, PathRecord },
+    colour::{ Colour, to_rgb8, write_colour, luminance },
+    exr::{ Channel, write_exr },
+    filter::Filter,
+    rtweekend::splitmix64,
+};
+
+// Running statistics of the samples taken for one pixel, and the filtered samples splatted into
+// it from itself and its neighbours.
+#[derive(Copy, Clone, Default)]
+pub struct Pixel {
+    pub sum: Colour,
+    pub sum_luminance: f64,
+    pub sum_luminance_sq: f64,
+    pub samples: i32,
+    pub weighted_sum: Colour,
+    pub weight_sum: f64,
+}
+
+impl Pixel {
+    pub fn colour(&self) -> Colour {
+        // The filtered pixel value. Filters with negative lobes can leave a pixel with no
+        // positive weight, and then the plain mean of its own samples stands in.
+        if self.weight_sum <= 0.0 {
+            if self.samples == 0 {
+                return Colour::default();
+            }
+            return self.sum / (self.samples as f64);
+        }
+        self.weighted_sum / self.weight_sum
+    }
+
+    pub fn add_sample(&mut self, colour: Colour) {
+        let l = luminance(colour);
+        self.sum += colour;
+        self.sum_luminance += l;
+        self.sum_luminance_sq += l * l;
+        self.samples += 1;
+    }
+
+    pub fn variance(&self) -> f64 {
+        // Estimated variance of the mean of the pixel's luminance.
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = self.sum_luminance / n;
+        let variance = ((self.sum_luminance_sq - n * mean * mean) / (n - 1.0)).max(0.0);
+        variance / n
+    }
+
+    pub fn error(&self) -> f64 {
+        // Estimated standard error of the pixel's luminance after gamma correction, so that the
+        // threshold means the same thing in dark and bright parts of the image.
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let mean = self.sum_luminance / (self.samples as f64);
+        let standard_error = self.variance().sqrt();
+
+        // d(sqrt(x))/dx = 1 / (2 sqrt(x)), matching linear_to_gamma.
+        standard_error / (2.0 * mean.max(1e-4).sqrt())
+    }
+}
+
+// A rectangle of pixels, from x0,y0 up to but not including x1,y1.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct Region {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl Region {
+    pub fn contains(&self, i: i32, j: i32) -> bool {
+        i >= self.x0 && i < self.x1 && j >= self.y0 && j < self.y1
+    }
+
+    pub fn clamp(&self, width: i32, height: i32) -> Option<Region> {
+        // The part of the region inside an image of this size, if any.
+        let region = Region {
+            x0: self.x0.max(0),
+            y0: self.y0.max(0),
+            x1: self.x1.min(width),
+            y1: self.y1.min(height),
+        };
+        if region.x0 >= region.x1 || region.y0 >= region.y1 {
+            return None;
+        }
+        Some(region)
+    }
+}
+
+// The accumulation buffer a render writes its samples into.
+#[derive(Clone)]
+pub struct Film {
+    pub width: i32,
+    pub height: i32,
+    pub filter: Filter,
+    pub aovs: Vec<Aov>,
+    pub aov_data: Vec<f64>, // Sums of each pixel's AOV channels, one pixel after another
+    pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(width: i32, height: i32, filter: Filter, aovs: Vec<Aov>) -> Self {
+        let pixel_count = (width * height) as usize;
+        let aov_channels: usize = aovs
+            .iter()
+            .map(|aov| aov.channels().len())
+            .sum();
+        Film {
+            width,
+            height,
+            filter,
+            aovs,
+            aov_data: vec![0.0; pixel_count * aov_channels],
+            pixels: vec![Pixel::default(); pixel_count],
+        }
+    }
+
+    pub fn cropped(&self, region: &Region) -> Film {
+        // A copy of the pixels inside the region.
+        let width = region.x1 - region.x0;
+        let height = region.y1 - region.y0;
+        let stride = self.aov_channels();
+        let mut film = Film::new(width, height, self.filter, self.aovs.clone());
+        for j in 0..height {
+            for i in 0..width {
+                let from = ((j + region.y0) * self.width + i + region.x0) as usize;
+                let to = (j * width + i) as usize;
+                film.pixels[to] = self.pixels[from];
+                film.aov_data[to * stride..(to + 1) * stride].copy_from_slice(
+                    &self.aov_data[from * stride..(from + 1) * stride]
+                );
+            }
+        }
+        film
+    }
+
+    pub fn masked(&self, region: &Region) -> Film {
+        // A copy of the film with the pixels outside the region cleared, including any samples
+        // the filter splatted over the region's edge.
+        let mut film = self.cropped(&Region { x0: 0, y0: 0, x1: self.width, y1: self.height });
+        let stride = self.aov_channels();
+        for j in 0..self.height {
+            for i in 0..self.width {
+                if !region.contains(i, j) {
+                    let index = (j * self.width + i) as usize;
+                    film.pixels[index] = Pixel::default();
+                    film.aov_data[index * stride..(index + 1) * stride].fill(0.0);
+                }
+            }
+        }
+        film
+    }
+
+    pub fn aov_channels(&self) -> usize {
+        self.aovs
+            .iter()
+            .map(|aov| aov.channels().len())
+            .sum()
+    }
+
+    pub fn add_sample(
+        &mut self,
+        i: i32,
+        j: i32,
+        offset: (f64, f64),
+        colour: Colour,
+        path: &PathRecord
+    ) {
+        // Record a sample taken at offset from the centre of pixel i,j, and splat it into every
+        // pixel whose centre is within the filter radius. AOVs are averaged over the samples
+        // taken in each pixel.
+        let first_sample = self.pixel(i, j).samples == 0;
+        self.pixel_mut(i, j).add_sample(colour);
+
+        let stride = self.aov_channels();
+        let mut index = ((j * self.width + i) as usize) * stride;
+        for aov in &self.aovs {
+            let value = aov.sample(path, colour);
+            for channel in value.iter().take(aov.channels().len()) {
+                if aov.is_id() {
+                    if first_sample {
+                        self.aov_data[index] = *channel;
+                    }
+                } else {
+                    self.aov_data[index] += channel;
+                }
+                index += 1;
+            }
+        }
+
+        let x = (i as f64) + offset.0;
+        let y = (j as f64) + offset.1;
+        let r = self.filter.radius;
+        let x0 = ((x - r).ceil() as i32).max(0);
+        let x1 = ((x + r).floor() as i32).min(self.width - 1);
+        let y0 = ((y - r).ceil() as i32).max(0);
+        let y1 = ((y + r).floor() as i32).min(self.height - 1);
+
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                // Half-open like the pixel footprint, so a box filter takes every sample of its
+                // own pixel, including those at offset -0.5.
+                let (dx, dy) = ((px as f64) - x, (py as f64) - y);
+                if dx <= -r || dx > r || dy <= -r || dy > r {
+                    continue;
+                }
+                let weight = self.filter.evaluate(dx, dy);
+                let pixel = self.pixel_mut(px, py);
+                pixel.weighted_sum += weight * colour;
+                pixel.weight_sum += weight;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, tile: &Film, x0: i32, y0: i32) {
+        // Add the samples of a film covering the pixels from x0,y0 onwards into this one.
+        let stride = self.aov_channels();
+        let ids: Vec<bool> = self.aovs
+            .iter()
+            .flat_map(|aov| aov.channels().iter().map(move |_| aov.is_id()))
+            .collect();
+
+        for j in 0..tile.height {
+            for i in 0..tile.width {
+                let from = (j * tile.width + i) as usize;
+                let to = ((j + y0) * self.width + i + x0) as usize;
+                let source = &tile.pixels[from];
+                let first_samples = self.pixels[to].samples == 0 && source.samples > 0;
+
+                let pixel = &mut self.pixels[to];
+                pixel.sum += source.sum;
+                pixel.sum_luminance += source.sum_luminance;
+                pixel.sum_luminance_sq += source.sum_luminance_sq;
+                pixel.samples += source.samples;
+                pixel.weighted_sum += source.weighted_sum;
+                pixel.weight_sum += source.weight_sum;
+
+                for (channel, is_id) in ids.iter().enumerate() {
+                    let value = tile.aov_data[from * stride + channel];
+                    if !is_id {
+                        self.aov_data[to * stride + channel] += value;
+                    } else if first_samples {
+                        self.aov_data[to * stride + channel] = value;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn pixel_at(&self, index: usize) -> &Pixel {
+        &self.pixels[index]
+    }
+
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Pixel] {
+        &mut self.pixels
+    }
+
+    pub fn pixel(&self, i: i32, j: i32) -> &Pixel {
+        &self.pixels[(j * self.width + i) as usize]
+    }
+
+    pub fn pixel_mut(&mut self, i: i32, j: i32) -> &mut Pixel {
+        &mut self.pixels[(j * self.width + i) as usize]
+    }
+
+    pub fn colours(&self) -> Vec<Colour> {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.colour())
+            .collect()
+    }
+
+    pub fn write_ppm(&self, filename: &str) -> std::io::Result<()> {
+        self.write_colours(filename, &self.colours())
+    }
+
+    pub fn write_colours(&self, filename: &str, colours: &[Colour]) -> std::io::Result<()> {
+        // Writes an image the size of the film, such as the film's colours after denoising.
+        let mut image_file = File::create(filename.to_string() + ".ppm")?;
+        image_file.write_all(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes())?;
+
+        for colour in colours {
+            write_colour(&mut image_file, *colour, 1);
+        }
+        Ok(())
+    }
+
+    pub fn aov_image(&self, aov: Aov) -> Option<Vec<[f64; 3]>> {
+        // Every pixel's value of the AOV, averaged over its samples, if the film records it.
+        let aov_index = self.aovs.iter().position(|a| *a == aov)?;
+        let offset: usize = self.aovs[..aov_index]
+            .iter()
+            .map(|aov| aov.channels().len())
+            .sum();
+        let stride = self.aov_channels();
+
+        let image = (0..self.pixels.len())
+            .map(|pixel_index| {
+                let start = pixel_index * stride + offset;
+                let samples = self.pixels[pixel_index].samples.max(1) as f64;
+                let mut value = [0.0; 3];
+                for (c, v) in value.iter_mut().take(aov.channels().len()).enumerate() {
+                    *v = self.aov_data[start + c];
+                    if !aov.is_id() {
+                        *v /= samples;
+                    }
+                }
+                value
+            })
+            .collect();
+        Some(image)
+    }
+
+    pub fn write_aov_ppms(&self, filename: &str, aovs: &[Aov]) -> std::io::Result<()> {
+        // Writes each AOV to its own image, named after the render with the AOV's name appended.
+        // Data that isn't a colour is scaled into the visible range.
+        for aov in aovs {
+            let values = self.aov_image(*aov).expect("AOV not recorded");
+            let max_depth = values
+                .iter()
+                .map(|v| v[0])
+                .fold(0.0, f64::max)
+                .max(1e-6);
+
+            let mut image_file = File::create(format!("{}_{}.ppm", filename, aov.name()))?;
+            image_file.write_all(
+                format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes()
+            )?;
+
+            for v in values {
+                let display = match aov {
+                    Aov::Depth => Colour::new(v[0], v[0], v[0]) / max_depth,
+                    Aov::Normal => 0.5 * (Colour::new(v[0], v[1], v[2]) + Colour::new(1.0, 1.0, 1.0)),
+                    Aov::Uv => Colour::new(v[0], v[1], 0.0),
+                    Aov::ObjectId | Aov::MaterialId => id_colour(v[0] as i32),
+                    _ => {
+                        write_colour(&mut image_file, Colour::new(v[0], v[1], v[2]), 1);
+                        continue;
+                    }
+                };
+                // write_colour applies gamma correction, so square the colour to write it as-is.
+                write_colour(&mut image_file, display * display, 1);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_exr(
+        &self,
+        filename: &str,
+        colours: &[Colour],
+        aovs: &[Aov]
+    ) -> std::io::Result<()> {
+        // Writes the render's colours as R, G and B and each AOV as a layer of a multi-channel
+        // EXR, all in linear floating point.
+        let mut channels: Vec<Channel> = Vec::new();
+        for (c, name) in ["R", "G", "B"].iter().enumerate() {
+            channels.push(Channel {
+                name: name.to_string(),
+                values: colours
+                    .iter()
+                    .map(|colour| colour.index(c) as f32)
+                    .collect(),
+            });
+        }
+
+        for aov in aovs {
+            let values = self.aov_image(*aov).expect("AOV not recorded");
+            for (c, name) in aov.channels().iter().enumerate() {
+                channels.push(Channel {
+                    name: format!("{}.{}", aov.name(), name),
+                    values: values
+                        .iter()
+                        .map(|v| v[c] as f32)
+                        .collect(),
+                });
+            }
+        }
+
+        write_exr(&(filename.to_string() + ".exr"), self.width, self.height, &mut channels)
+    }
+
+    pub fn write_sample_heatmap(&self, filename: &str) -> std::io::Result<()> {
+        // Writes the number of samples each pixel received, from blue (fewest) to red (most).
+        let max_samples = self.pixels
+            .iter()
+            .map(|p| p.samples)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+
+        let mut image_file = File::create(filename.to_string() + ".ppm")?;
+        image_file.write_all(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes())?;
+
+        for pixel in &self.pixels {
+            let t = (pixel.samples as f64) / (max_samples as f64);
+            let heat = Colour::new(t, 1.0 - (2.0 * t - 1.0).abs(), 1.0 - t);
+            // write_colour applies gamma correction, so square the colour to write it as-is.
+            write_colour(&mut image_file, heat * heat, 1);
+        }
+        Ok(())
+    }
+}
+
+fn id_colour(id: i32) -> Colour {
+    // A bright colour picked at random for each ID, and black for 0.
+    if id == 0 {
+        return Colour::default();
+    }
+    let hash = splitmix64(id as u64);
+    let channel = |shift: u32| 0.25 + 0.75 * (((hash >> shift) & 0xff) as f64) / 255.0;
+    Colour::new(channel(0), channel(8), channel(16))
+}
+
+// A rendered image in memory: each pixel's linear colour, in rows from the top left.
+#[derive(Debug, Clone, Default)]
+pub struct Framebuffer {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<Colour>,
+}
+
+impl Framebuffer {
+    pub fn pixel(&self, i: i32, j: i32) -> Colour {
+        self.pixels[(j * self.width + i) as usize]
+    }
+
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        // The pixels as gamma-corrected 8-bit RGB, three bytes per pixel.
+        self.pixels
+            .iter()
+            .flat_map(|colour| to_rgb8(*colour))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_filter_keeps_samples_on_the_pixel_edge() {
+        let mut film = Film::new(3, 1, Filter::from_name("box", None).unwrap(), Vec::new());
+        let colour = Colour::new(1.0, 0.5, 0.25);
+        film.add_sample(1, 0, (-0.5, -0.5), colour, &PathRecord::default());
+        assert_eq!(film.pixel(1, 0).weight_sum, 1.0);
+        assert_eq!(film.pixel(1, 0).colour(), colour);
+        assert_eq!(film.pixel(0, 0).weight_sum, 0.0);
+    }
+
+    #[test]
+    fn negative_weights_fall_back_to_the_mean() {
+        // Only neighbouring samples in a Mitchell filter's negative lobes reached the pixel.
+        let pixel = Pixel {
+            sum: Colour::new(1.0, 0.5, 0.0),
+            samples: 2,
+            weighted_sum: Colour::new(-0.02, -0.01, 0.0),
+            weight_sum: -0.04,
+            ..Pixel::default()
+        };
+        assert_eq!(pixel.colour(), Colour::new(0.5, 0.25, 0.0));
+    }
+}
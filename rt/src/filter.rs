@@ -0,0 +1,149 @@
+use std::f64::consts::PI;
+
+use serde::Deserialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+    Lanczos,
+}
+
+// A separable pixel reconstruction filter. Every sample is splatted into the pixels whose
+// centres lie within `radius` of it (in pixel units), weighted by the filter.
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: f64) -> Self {
+        Filter { kind, radius: radius.max(0.5) }
+    }
+
+    pub fn from_name(name: &str, radius: Option<f64>) -> Result<Self, String> {
+        let (kind, default_radius) = match name.to_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "box" => (FilterKind::Box, 0.5),
+            "tent" | "triangle" => (FilterKind::Tent, 1.0),
+            "gaussian" => (FilterKind::Gaussian, 1.5),
+            "mitchell" | "mitchellnetravali" => (FilterKind::Mitchell, 2.0),
+            "blackmanharris" => (FilterKind::BlackmanHarris, 2.0),
+            "lanczos" => (FilterKind::Lanczos, 3.0),
+            _ => {
+                return Err(format!("unknown filter '{}'", name));
+            }
+        };
+        Ok(Filter::new(kind, radius.unwrap_or(default_radius)))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::BlackmanHarris => "blackman-harris",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        let x = x.abs();
+        if x > r {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                // Standard deviation of half a pixel, shifted down so it reaches zero at r.
+                let gaussian = |x: f64| (-2.0 * x * x).exp();
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterKind::Mitchell => {
+                // Mitchell-Netravali with B = C = 1/3, stretched from [-2,2] to [-r,r].
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let t = (2.0 * x) / r;
+                if t < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * t * t * t +
+                        (-18.0 + 12.0 * b + 6.0 * c) * t * t +
+                        (6.0 - 2.0 * b)) /
+                        6.0
+                } else {
+                    ((-b - 6.0 * c) * t * t * t +
+                        (6.0 * b + 30.0 * c) * t * t +
+                        (-12.0 * b - 48.0 * c) * t +
+                        (8.0 * b + 24.0 * c)) /
+                        6.0
+                }
+            }
+            FilterKind::BlackmanHarris => {
+                let t = (x + r) / (2.0 * r);
+                0.35875 - 0.48829 * (2.0 * PI * t).cos() + 0.14128 * (4.0 * PI * t).cos() -
+                    0.01168 * (6.0 * PI * t).cos()
+            }
+            FilterKind::Lanczos => {
+                // A sinc windowed by a sinc stretched to reach its first zero at r.
+                let sinc = |x: f64| if x < 1e-5 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                sinc(x) * sinc(x / r)
+            }
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        // A half-pixel box only weights samples into the pixel they were taken in.
+        Filter::new(FilterKind::Box, 0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_peak_at_the_centre_and_fade_out_at_the_radius() {
+        // Each filter at its default radius: its value at the centre, and at the radius.
+        let cases = [
+            ("box", 1.0, 1.0),
+            ("tent", 1.0, 0.0),
+            ("gaussian", 1.0 - (-4.5_f64).exp(), 0.0),
+            ("mitchell", 8.0 / 9.0, 0.0),
+            ("blackman-harris", 1.0, 0.00006),
+            ("lanczos", 1.0, 0.0),
+        ];
+        for (name, centre, edge) in cases {
+            let filter = Filter::from_name(name, None).unwrap();
+            let r = filter.radius;
+            assert!((filter.evaluate(0.0, 0.0) - centre * centre).abs() < 1e-9, "{}", name);
+            assert!((filter.evaluate(r, 0.0) - edge * centre).abs() < 1e-9, "{}", name);
+            assert!((filter.evaluate(0.0, -r) - edge * centre).abs() < 1e-9, "{}", name);
+            assert_eq!(filter.evaluate(r + 0.01, 0.0), 0.0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn mitchell_and_lanczos_have_negative_lobes() {
+        // Mitchell dips below zero over the outer half of its radius, and Lanczos between its
+        // first and second zeros, one and two pixels out.
+        let mitchell = Filter::from_name("mitchell", None).unwrap();
+        assert!(mitchell.evaluate(1.5, 0.0) < 0.0);
+        assert!(mitchell.evaluate(0.5, 0.0) > 0.0);
+        let lanczos = Filter::from_name("lanczos", None).unwrap();
+        assert!(lanczos.evaluate(1.5, 0.0) < 0.0);
+        assert!(lanczos.evaluate(0.5, 0.0) > 0.0);
+        assert!(lanczos.evaluate(2.5, 0.0) > 0.0);
+        assert!(lanczos.evaluate(1.0, 0.0).abs() < 1e-9);
+    }
+}
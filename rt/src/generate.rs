@@ -0,0 +1,315 @@
+use serde_yaml::{ Mapping, Value };
+
+use crate::rtweekend::{ random_f64, start_sample_stream };
+
+// Built-in scenes for benchmarks and tests, generated from a seed so the same command always gives
+// the same scene. The scene's camera uses the seed too, so renders of it are repeatable.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Generator {
+    RandomSpheres, // A field of small Lambertian, metal and glass spheres around three large ones
+    CornellBox, // An open-topped Cornell box with two blocks, lit by the sky
+    SphereGrid, // A grid of spheres going from rough to smooth and from dielectric to metal
+}
+
+impl Generator {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().replace('_', "-").as_str() {
+            "random-spheres" => Ok(Generator::RandomSpheres),
+            "cornell-box" => Ok(Generator::CornellBox),
+            "sphere-grid" => Ok(Generator::SphereGrid),
+            _ => Err(format!("unknown scene '{}' to generate", name)),
+        }
+    }
+}
+
+pub fn generate(generator: Generator, count: Option<usize>, seed: u64) -> Result<Value, String> {
+    // The generated scene, using the thread's random number generator from the seed.
+    start_sample_stream(seed, 0, 0);
+    match generator {
+        Generator::RandomSpheres => Ok(random_spheres(count.unwrap_or(484), seed)),
+        Generator::CornellBox => {
+            if count.is_some() {
+                return Err("cornell-box has no --count".to_string());
+            }
+            Ok(cornell_box(seed))
+        }
+        Generator::SphereGrid => Ok(sphere_grid(count.unwrap_or(100).max(1), seed)),
+    }
+}
+
+fn random_spheres(count: usize, seed: u64) -> Value {
+    // After the final scene of Ray Tracing in One Weekend: small spheres jittered over a grid of
+    // cells, wide enough to hold count of them, leaving room for the three large spheres.
+    let mut materials = vec![
+        material("ground", lambertian([0.5, 0.5, 0.5])),
+        material("glass", glass()),
+        material("brown", lambertian([0.4, 0.2, 0.1])),
+        material("mirror", metal([0.7, 0.6, 0.5]))
+    ];
+    let mut objects = vec![
+        plane([-500.0, 0.0, -500.0], [1000.0, 0.0, 0.0], [0.0, 0.0, 1000.0], "ground"),
+        sphere([0.0, 1.0, 0.0], 1.0, "glass"),
+        sphere([-4.0, 1.0, 0.0], 1.0, "brown"),
+        sphere([4.0, 1.0, 0.0], 1.0, "mirror")
+    ];
+
+    let large = [[0.0, 0.2, 0.0], [-4.0, 0.2, 0.0], [4.0, 0.2, 0.0]];
+    let side = ((count as f64) + 16.0).sqrt().ceil() as i32 + 1;
+    let mut placed = 0;
+    'cells: for a in -side / 2..side - side / 2 {
+        for b in -side / 2..side - side / 2 {
+            if placed == count {
+                break 'cells;
+            }
+            let choose_mat = random_f64();
+            let centre = [(a as f64) + 0.9 * random_f64(), 0.2, (b as f64) + 0.9 * random_f64()];
+            let clear = large.iter().all(|point| {
+                let (dx, dz) = (centre[0] - point[0], centre[2] - point[2]);
+                (dx * dx + dz * dz).sqrt() > 0.9
+            });
+            if !clear {
+                continue;
+            }
+
+            placed += 1;
+            let name = if choose_mat < 0.95 {
+                let name = format!("sphere{}", placed);
+                let properties = if choose_mat < 0.8 {
+                    let colour = [
+                        random_f64() * random_f64(),
+                        random_f64() * random_f64(),
+                        random_f64() * random_f64(),
+                    ];
+                    lambertian(colour)
+                } else {
+                    let colour = [
+                        0.5 + 0.5 * random_f64(),
+                        0.5 + 0.5 * random_f64(),
+                        0.5 + 0.5 * random_f64(),
+                    ];
+                    metal(colour)
+                };
+                materials.push(material(&name, properties));
+                name
+            } else {
+                "glass".to_string()
+            };
+            objects.push(sphere(centre, 0.2, &name));
+        }
+    }
+
+    let camera = camera(
+        vec![
+            ("image_width", Value::from(400)),
+            ("samples_per_pixel", Value::from(100)),
+            ("max_depth", Value::from(50)),
+            ("vfov", Value::from(20.0)),
+            ("lookfrom", vector([13.0, 2.0, 3.0])),
+            ("lookat", vector([0.0, 0.0, 0.0]))
+        ],
+        seed
+    );
+    scene(camera, materials, objects, "random_spheres")
+}
+
+fn cornell_box(seed: u64) -> Value {
+    // The sky is the only light, so the box has no ceiling and light falls in from above as well
+    // as through the open front. The camera looks along +z, so +x is on the left of the image.
+    let materials = vec![
+        material("white", lambertian([0.73, 0.73, 0.73])),
+        material("red", lambertian([0.65, 0.05, 0.05])),
+        material("green", lambertian([0.12, 0.45, 0.15]))
+    ];
+    let mut tall_block = cuboid([-0.3, 0.0, -0.3], [0.3, 1.2, 0.3], "white");
+    let mut short_block = cuboid([-0.3, 0.0, -0.3], [0.3, 0.6, 0.3], "white");
+    place(&mut tall_block, -15.0, [0.35, 0.0, 0.3]);
+    place(&mut short_block, 18.0, [-0.35, 0.0, -0.35]);
+    let objects = vec![
+        plane([-1.0, 0.0, -1.0], [2.0, 0.0, 0.0], [0.0, 0.0, 2.0], "white"),
+        plane([-1.0, 0.0, 1.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0], "white"),
+        plane([1.0, 0.0, -1.0], [0.0, 0.0, 2.0], [0.0, 2.0, 0.0], "red"),
+        plane([-1.0, 0.0, -1.0], [0.0, 0.0, 2.0], [0.0, 2.0, 0.0], "green"),
+        tall_block,
+        short_block
+    ];
+
+    let camera = camera(
+        vec![
+            ("aspect_ratio", Value::from(1.0)),
+            ("image_width", Value::from(400)),
+            ("samples_per_pixel", Value::from(200)),
+            ("max_depth", Value::from(50)),
+            ("vfov", Value::from(40.0)),
+            ("lookfrom", vector([0.0, 1.0, -3.9])),
+            ("lookat", vector([0.0, 1.0, 0.0]))
+        ],
+        seed
+    );
+    scene(camera, materials, objects, "cornell_box")
+}
+
+fn sphere_grid(count: usize, seed: u64) -> Value {
+    // Spheres in rows from the front, filling a square grid. Metallic goes from 0 to 1 along
+    // each row from the left, and roughness from 0 to 1 from the front row to the back.
+    let side = (count as f64).sqrt().ceil() as usize;
+    let step = |i: usize| if side > 1 { (i as f64) / ((side - 1) as f64) } else { 0.0 };
+    let offset = ((side - 1) as f64) / 2.0;
+
+    let mut materials = vec![material("ground", lambertian([0.5, 0.5, 0.5]))];
+    let mut objects = vec![
+        plane([-500.0, -0.4, -500.0], [1000.0, 0.0, 0.0], [0.0, 0.0, 1000.0], "ground")
+    ];
+    for i in 0..count {
+        let (column, row) = (i % side, i / side);
+        let name = format!("sphere{}", i + 1);
+        let mut properties = Mapping::new();
+        properties.insert(Value::from("type"), Value::from("Pbr"));
+        properties.insert(Value::from("colour"), vector([0.8, 0.3, 0.2]));
+        properties.insert(Value::from("metallic"), Value::from(round(step(column))));
+        properties.insert(Value::from("roughness"), Value::from(round(step(row))));
+        materials.push(material(&name, properties));
+
+        // The camera looks along +z, so +x is on the left of the image.
+        let centre = [offset - (column as f64), 0.0, (row as f64) - offset];
+        objects.push(sphere(centre, 0.4, &name));
+    }
+
+    let distance = (side as f64).max(2.0);
+    let camera = camera(
+        vec![
+            ("image_width", Value::from(400)),
+            ("samples_per_pixel", Value::from(100)),
+            ("max_depth", Value::from(50)),
+            ("vfov", Value::from(30.0)),
+            ("lookfrom", vector([0.0, 0.8 * distance, -1.6 * distance])),
+            ("lookat", vector([0.0, 0.0, 0.0]))
+        ],
+        seed
+    );
+    scene(camera, materials, objects, "sphere_grid")
+}
+
+fn scene(camera: Value, materials: Vec<Value>, objects: Vec<Value>, filename: &str) -> Value {
+    let mut scene = Mapping::new();
+    scene.insert(Value::from("camera"), camera);
+    scene.insert(Value::from("materials"), Value::Sequence(materials));
+    scene.insert(Value::from("objects"), Value::Sequence(objects));
+    scene.insert(Value::from("filename"), Value::from(filename));
+    Value::Mapping(scene)
+}
+
+fn camera(settings: Vec<(&str, Value)>, seed: u64) -> Value {
+    let mut camera = Mapping::new();
+    for (key, value) in settings {
+        camera.insert(Value::from(key), value);
+    }
+    camera.insert(Value::from("seed"), Value::from(seed));
+    Value::Mapping(camera)
+}
+
+fn material(name: &str, properties: Mapping) -> Value {
+    // Materials are single-key maps from their name to their settings.
+    let mut entry = Mapping::new();
+    entry.insert(Value::from(name), Value::Mapping(properties));
+    Value::Mapping(entry)
+}
+
+fn lambertian(colour: [f64; 3]) -> Mapping {
+    let mut properties = Mapping::new();
+    properties.insert(Value::from("type"), Value::from("Lambertian"));
+    properties.insert(Value::from("colour"), vector(colour));
+    properties
+}
+
+fn metal(colour: [f64; 3]) -> Mapping {
+    let mut properties = Mapping::new();
+    properties.insert(Value::from("type"), Value::from("Metal"));
+    properties.insert(Value::from("colour"), vector(colour));
+    properties
+}
+
+fn glass() -> Mapping {
+    // Glass is a fully transmissive, smooth Pbr material.
+    let mut properties = Mapping::new();
+    properties.insert(Value::from("type"), Value::from("Pbr"));
+    properties.insert(Value::from("colour"), vector([1.0, 1.0, 1.0]));
+    properties.insert(Value::from("roughness"), Value::from(0.0));
+    properties.insert(Value::from("transmission"), Value::from(1.0));
+    properties.insert(Value::from("ior"), Value::from(1.5));
+    properties
+}
+
+fn plane(q: [f64; 3], u: [f64; 3], v: [f64; 3], mat: &str) -> Value {
+    object("Plane", vec![("q", vector(q)), ("u", vector(u)), ("v", vector(v))], mat)
+}
+
+fn sphere(centre: [f64; 3], radius: f64, mat: &str) -> Value {
+    object("Sphere", vec![("centre", vector(centre)), ("radius", Value::from(radius))], mat)
+}
+
+fn cuboid(a: [f64; 3], b: [f64; 3], mat: &str) -> Value {
+    object("Cuboid", vec![("a", vector(a)), ("b", vector(b))], mat)
+}
+
+fn object(object_type: &str, settings: Vec<(&str, Value)>, mat: &str) -> Value {
+    let mut object = Mapping::new();
+    object.insert(Value::from("type"), Value::from(object_type));
+    for (key, value) in settings {
+        object.insert(Value::from(key), value);
+    }
+    object.insert(Value::from("mat"), Value::from(mat));
+    Value::Mapping(object)
+}
+
+fn place(object: &mut Value, rotate_y: f64, translate: [f64; 3]) {
+    // Turn the object about the y axis, then move it.
+    let object = object.as_mapping_mut().unwrap();
+    object.insert(Value::from("rotate_y"), Value::from(rotate_y));
+    object.insert(Value::from("translate"), vector(translate));
+}
+
+fn vector(value: [f64; 3]) -> Value {
+    Value::Sequence(value.iter().map(|&component| Value::from(round(component))).collect())
+}
+
+fn round(value: f64) -> f64 {
+    // Three decimal places keep the generated files short and readable.
+    (value * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::parse_scene;
+
+    const GENERATORS: [(Generator, Option<usize>); 3] = [
+        (Generator::RandomSpheres, Some(20)),
+        (Generator::CornellBox, None),
+        (Generator::SphereGrid, Some(9)),
+    ];
+
+    #[test]
+    fn generated_scenes_parse() {
+        for (generator, count) in GENERATORS {
+            let scene = generate(generator, count, 1).unwrap();
+            let text = serde_yaml::to_string(&scene).unwrap();
+            let (world, _, _) = parse_scene("generated.yaml", &text).unwrap();
+            // Every object was built, none skipped as unknown.
+            let objects = scene["objects"].as_sequence().unwrap().len();
+            assert_eq!(world.objects.len(), objects, "{:?}", generator);
+        }
+        // The three large spheres and the ground, then the small ones.
+        let spheres = generate(Generator::RandomSpheres, Some(20), 1).unwrap();
+        assert_eq!(spheres["objects"].as_sequence().unwrap().len(), 24);
+    }
+
+    #[test]
+    fn seeds_give_repeatable_scenes() {
+        for (generator, count) in GENERATORS {
+            let scene = generate(generator, count, 7).unwrap();
+            assert_eq!(scene, generate(generator, count, 7).unwrap(), "{:?}", generator);
+            assert_ne!(scene, generate(generator, count, 8).unwrap(), "{:?}", generator);
+        }
+    }
+}
@@ -1,25 +1,68 @@
-use std::{ collections::HashMap, rc::Rc };
+use std::{ collections::HashMap, sync::Arc, time::Duration };
 
 use serde::de::DeserializeOwned;
-use serde_yaml::Value;
+use serde_yaml::{ Mapping, Value };
 
 use crate::{
-    material::{ Material, Lambertian, Metal },
+    material::{
+        Material,
+        Lambertian,
+        Metal,
+        HenyeyGreenstein,
+        Pbr,
+        PbrParameters,
+        Mix,
+        MixFactor,
+        Coated,
+        NormalPerturbation,
+        Perturbed,
+        Subsurface,
+    },
+    image::Image,
     colour::Colour,
-    hittable::Hittable,
+    hittable::{ Hittable, Identified, RotateY, Translate },
     sphere::Sphere,
     cylinder::Cylinder,
     vector3::Vector3,
     quad::{ Plane, cuboid },
+    volume::{ VoxelGrid, HeterogeneousMedium },
+    registry::{ Materials, material_constructor, object_constructor },
 };
 
+// Settings a scene file can leave out, read by the extract_* parsers and written out by the
+// canonical_* converters.
+const DEFAULT_MATERIAL: &str = "Lambertian"; // Also used for unknown material types
+const DEFAULT_COLOUR: [f64; 3] = [1.0, 1.0, 1.0];
+const BUMP_SCALE: f64 = 0.01;
+const MIX_FACTOR: f64 = 0.5;
+const MIX_FRESNEL_IOR: f64 = 1.5;
+const SUBSURFACE_MEAN_FREE_PATH: [f64; 3] = [0.1, 0.1, 0.1];
+const SUBSURFACE_IOR: f64 = 1.4;
+const SUBSURFACE_ANISOTROPY: f64 = 0.0;
+const SUBSURFACE_MAX_STEPS: i32 = 256;
+const COATED_IOR: f64 = 1.5;
+const COATED_ROUGHNESS: f64 = 0.0;
+const PLANE_Q: [f64; 3] = [-6.0, -0.5, -6.0];
+const PLANE_U: [f64; 3] = [12.0, 0.0, 0.0];
+const PLANE_V: [f64; 3] = [0.0, 0.0, 12.0];
+const SPHERE_CENTRE: [f64; 3] = [0.0, 0.0, 0.0];
+const SPHERE_RADIUS: f64 = 1.0;
+const CUBOID_A: [f64; 3] = [-1.0, -0.5, -1.0];
+const CUBOID_B: [f64; 3] = [1.0, 0.5, 1.0];
+const CYLINDER_CENTRE: [f64; 3] = [0.0, 0.5, 0.0];
+const CYLINDER_RADIUS: f64 = 0.6;
+const CYLINDER_HEIGHT: f64 = 1.0;
+const VOLUME_DENSITY: f64 = 1.0;
+const VOLUME_ANISOTROPY: f64 = 0.0;
+const VOLUME_COLOUR: [f64; 3] = [1.0, 1.0, 1.0];
+
 // Helper function to get values from nested YAML structures
 // pub fn get_yaml_value<T>(yaml_value: &Value, key: &str) -> Result<T, Box<dyn std::error::Error>>
 //     where T: serde::de::DeserializeOwned
 // {
 //     match yaml_value {
 //         Value::Mapping(mapping) => {
-//             if let Some(value) = mapping.get(&serde_yaml::Value::String(key.to_string())) {
+//             if let Some(value) = mapping.get(serde_yaml::Value::String(key.to_string())) {
 //                 let result: T = serde_yaml::from_value(value.clone())?;
 //                 Ok(result)
 //             } else {
@@ -30,11 +73,11 @@ use crate::{
 //     }
 // }
 
-pub fn get_nested_yaml_value<'a, T>(yaml_value: &'a Value, key: &str) -> Option<T>
+pub fn get_nested_yaml_value<T>(yaml_value: &Value, key: &str) -> Option<T>
     where T: DeserializeOwned
 {
     if let Value::Mapping(mapping) = yaml_value {
-        if let Some(value) = mapping.get(&serde_yaml::Value::String(key.to_string())) {
+        if let Some(value) = mapping.get(serde_yaml::Value::String(key.to_string())) {
             if let Ok(deserialized_value) = serde_yaml::from_value(value.clone()) {
                 return Some(deserialized_value);
             }
@@ -45,45 +88,25 @@ pub fn get_nested_yaml_value<'a, T>(yaml_value: &'a Value, key: &str) -> Option<
 
 pub fn extract_materials(
     materials_value: &Value
-) -> Result<HashMap<String, Rc<dyn Material>>, Box<dyn std::error::Error>> {
+) -> Result<HashMap<String, Arc<dyn Material>>, Box<dyn std::error::Error>> {
     let mut materials = HashMap::new();
 
     if let Value::Sequence(materials_seq) = materials_value {
         for material_entry in materials_seq {
             if let Value::Mapping(material_mapping) = material_entry {
                 if let Some((material_name, material_properties)) = material_mapping.iter().next() {
-                    let material_type_value = get_nested_yaml_value(
-                        material_properties,
-                        "type"
-                    ).unwrap_or("Lambertian".to_string());
-
-                    let material_colour_values = get_nested_yaml_value(
-                        material_properties,
-                        "colour"
-                    ).unwrap_or([1.0, 1.0, 1.0]);
-
-                    let material_colour = Colour::new(
-                        material_colour_values[0],
-                        material_colour_values[1],
-                        material_colour_values[2]
-                    );
-
-                    let material: Rc<dyn Material> = match material_type_value.as_str() {
-                        "Lambertian" => Rc::new(Lambertian::new(material_colour)),
-                        "Metal" => Rc::new(Metal::new(material_colour)),
-                        _ => Rc::new(Lambertian::new(material_colour)),
-                    };
-
-                    let material_name_str = material_name
+                    let material_name = material_name
                         .as_str()
-                        .expect("material name is not a string");
-
-                    materials.insert(material_name_str.to_string(), material);
+                        .ok_or("material names must be text")?;
+                    let material = extract_material(material_properties, &materials).map_err(
+                        |err| format!("material '{}': {}", material_name, err)
+                    )?;
+                    materials.insert(material_name.to_string(), material);
                 } else {
-                    eprintln!("Material entry is missing 'type' or 'colour' key");
+                    return Err("material entries need a name".into());
                 }
             } else {
-                eprintln!("Invalid material entry in the YAML file");
+                return Err("material entries should be a name and its settings".into());
             }
         }
     } else {
@@ -93,119 +116,619 @@ pub fn extract_materials(
     Ok(materials)
 }
 
+fn extract_material(
+    material_properties: &Value,
+    materials: &Materials
+) -> Result<Arc<dyn Material>, Box<dyn std::error::Error>> {
+    let material_type_value = get_nested_yaml_value(
+        material_properties,
+        "type"
+    ).unwrap_or(DEFAULT_MATERIAL.to_string());
+
+    let material_colour_values = get_nested_yaml_value(
+        material_properties,
+        "colour"
+    ).unwrap_or(DEFAULT_COLOUR);
+
+    let material_colour = Colour::new(
+        material_colour_values[0],
+        material_colour_values[1],
+        material_colour_values[2]
+    );
+
+    let material = match material_constructor(&material_type_value) {
+        Some(constructor) => constructor(material_properties, materials)?,
+        None => {
+            builtin_material(&material_type_value, material_properties, material_colour, materials)?
+        }
+    };
+
+    extract_perturbation(material_properties, material)
+}
+
+fn builtin_material(
+    material_type: &str,
+    material_properties: &Value,
+    material_colour: Colour,
+    materials: &Materials
+) -> Result<Arc<dyn Material>, Box<dyn std::error::Error>> {
+    let material: Arc<dyn Material> = match material_type {
+        "Lambertian" => Arc::new(Lambertian::new(material_colour)),
+        "Metal" => Arc::new(Metal::new(material_colour)),
+        "Pbr" | "Principled" =>
+            Arc::new(Pbr::new(extract_pbr_parameters(material_properties, material_colour))),
+        "Mix" => {
+            let a = find_material(materials, material_properties, "a")?;
+            let b = find_material(materials, material_properties, "b")?;
+            let factor_map = get_nested_yaml_value::<String>(material_properties, "factor_map");
+            let factor = if let Some(path) = factor_map {
+                MixFactor::Texture(Image::load_ppm(&path)?)
+            } else {
+                match get_nested_yaml_value::<String>(material_properties, "factor") {
+                    Some(f) if f == "fresnel" =>
+                        MixFactor::Fresnel(
+                            get_nested_yaml_value(material_properties, "ior").unwrap_or(
+                                MIX_FRESNEL_IOR
+                            )
+                        ),
+                    _ =>
+                        MixFactor::Constant(
+                            get_nested_yaml_value(material_properties, "factor").unwrap_or(
+                                MIX_FACTOR
+                            )
+                        ),
+                }
+            };
+            Arc::new(Mix::new(a, b, factor))
+        }
+        "Subsurface" => {
+            let mfp = get_nested_yaml_value(
+                material_properties,
+                "mean_free_path"
+            ).unwrap_or(SUBSURFACE_MEAN_FREE_PATH);
+            let ior = get_nested_yaml_value(material_properties, "ior").unwrap_or(SUBSURFACE_IOR);
+            let anisotropy = get_nested_yaml_value(
+                material_properties,
+                "anisotropy"
+            ).unwrap_or(SUBSURFACE_ANISOTROPY);
+            let max_steps = get_nested_yaml_value(
+                material_properties,
+                "max_steps"
+            ).unwrap_or(SUBSURFACE_MAX_STEPS);
+            Arc::new(
+                Subsurface::new(
+                    material_colour,
+                    Colour::new(mfp[0], mfp[1], mfp[2]),
+                    ior,
+                    anisotropy,
+                    max_steps
+                )
+            )
+        }
+        "Coated" => {
+            let base = find_material(materials, material_properties, "base")?;
+            let ior = get_nested_yaml_value(material_properties, "ior").unwrap_or(COATED_IOR);
+            let roughness = get_nested_yaml_value(
+                material_properties,
+                "roughness"
+            ).unwrap_or(COATED_ROUGHNESS);
+            Arc::new(Coated::new(base, ior, roughness, material_colour))
+        }
+        _ => Arc::new(Lambertian::new(material_colour)),
+    };
+    Ok(material)
+}
+
+fn find_material(
+    materials: &HashMap<String, Arc<dyn Material>>,
+    material_properties: &Value,
+    key: &str
+) -> Result<Arc<dyn Material>, Box<dyn std::error::Error>> {
+    // Layered materials refer to other materials by name, which must be defined earlier in the
+    // 'materials' list.
+    let name: String = get_nested_yaml_value(material_properties, key).ok_or(
+        format!("material is missing the '{}' key", key)
+    )?;
+    match materials.get(&name) {
+        Some(material) => Ok(material.clone()),
+        None => Err(format!("material '{}' must be defined before it is referenced", name).into()),
+    }
+}
+
+fn extract_perturbation(
+    material_properties: &Value,
+    material: Arc<dyn Material>
+) -> Result<Arc<dyn Material>, Box<dyn std::error::Error>> {
+    // Any material can have its shading normal perturbed by a normal map or a bump map.
+    if let Some(path) = get_nested_yaml_value::<String>(material_properties, "normal_map") {
+        let image = Image::load_ppm(&path)?;
+        return Ok(Arc::new(Perturbed::new(material, NormalPerturbation::NormalMap(image))));
+    }
+    if let Some(path) = get_nested_yaml_value::<String>(material_properties, "bump_map") {
+        let image = Image::load_ppm(&path)?;
+        let scale = get_nested_yaml_value(material_properties, "bump_scale").unwrap_or(BUMP_SCALE);
+        return Ok(Arc::new(Perturbed::new(material, NormalPerturbation::BumpMap(image, scale))));
+    }
+    Ok(material)
+}
+
+fn extract_pbr_parameters(material_properties: &Value, base_colour: Colour) -> PbrParameters {
+    let defaults = PbrParameters::default();
+    let get = |key: &str, default: f64| get_nested_yaml_value(material_properties, key).unwrap_or(default);
+
+    PbrParameters {
+        base_colour,
+        metallic: get("metallic", defaults.metallic),
+        roughness: get("roughness", defaults.roughness),
+        specular: get("specular", defaults.specular),
+        ior: get("ior", defaults.ior),
+        transmission: get("transmission", defaults.transmission),
+        clearcoat: get("clearcoat", defaults.clearcoat),
+        clearcoat_roughness: get("clearcoat_roughness", defaults.clearcoat_roughness),
+        sheen: get("sheen", defaults.sheen),
+    }
+}
+
+pub fn extract_material_ids(materials_value: &Value) -> HashMap<String, i32> {
+    // Number the materials in the order they're listed, counting from 1.
+    let mut material_ids = HashMap::new();
+    if let Value::Sequence(materials_seq) = materials_value {
+        for material_entry in materials_seq {
+            if let Some((Value::String(name), _)) = material_entry.as_mapping().and_then(|m| m.iter().next()) {
+                material_ids.insert(name.clone(), (material_ids.len() as i32) + 1);
+            }
+        }
+    }
+    material_ids
+}
+
 pub fn extract_objects(
     objects_value: &Value,
-    materials: &HashMap<String, Rc<dyn Material>>
-) -> Result<Vec<Rc<dyn Hittable>>, Box<dyn std::error::Error>> {
-    let mut objects: Vec<Rc<dyn Hittable>> = Vec::new();
+    materials: &HashMap<String, Arc<dyn Material>>,
+    material_ids: &HashMap<String, i32>
+) -> Result<Vec<Arc<dyn Hittable>>, Box<dyn std::error::Error>> {
+    let mut objects: Vec<Arc<dyn Hittable>> = Vec::new();
 
     if let Value::Sequence(objects_seq) = objects_value {
-        for object_config in objects_seq {
-            let object_type: String = get_nested_yaml_value(object_config, "type").expect(
-                "no object defined"
+        for (index, object_config) in objects_seq.iter().enumerate() {
+            let object_type: String = get_nested_yaml_value(object_config, "type").ok_or(
+                format!("object {} has no type", index + 1)
+            )?;
+            let describe = |err: Box<dyn std::error::Error>| {
+                match get_nested_yaml_value::<String>(object_config, "name") {
+                    Some(name) => format!("object '{}': {}", name, err),
+                    None => format!("object {} ({}): {}", index + 1, object_type, err),
+                }
+            };
+
+            let object = match object_constructor(&object_type) {
+                Some(constructor) => constructor(object_config, materials).map_err(describe)?,
+                None => {
+                    let object = builtin_object(&object_type, object_config, materials);
+                    match object.map_err(describe)? {
+                        Some(object) => object,
+                        None => {
+                            // Handle unknown object types or log an error
+                            eprintln!("Unknown object type: {}", object_type);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            // Any object can be rotated about the y axis and then moved.
+            let mut object = object;
+            if let Some(angle) = get_nested_yaml_value::<f64>(object_config, "rotate_y") {
+                object = Arc::new(RotateY::new(object, angle));
+            }
+            if let Some(offset) = get_nested_yaml_value::<[f64; 3]>(object_config, "translate") {
+                let offset = Vector3::new(offset[0], offset[1], offset[2]);
+                object = Arc::new(Translate::new(object, offset));
+            }
+
+            let material_name: String = get_nested_yaml_value(object_config, "mat").unwrap_or_default();
+            let material_id = material_ids.get(&material_name).copied().unwrap_or(0);
+            let object_id = (objects.len() as i32) + 1;
+            objects.push(Arc::new(Identified::new(object, object_id, material_id)));
+        }
+    }
+
+    Ok(objects)
+}
+
+fn builtin_object(
+    object_type: &str,
+    object_config: &Value,
+    materials: &Materials
+) -> Result<Option<Arc<dyn Hittable>>, Box<dyn std::error::Error>> {
+    let object: Arc<dyn Hittable> = match object_type {
+        "Plane" => {
+            let q_value = get_nested_yaml_value(object_config, "q").unwrap_or(PLANE_Q);
+            let u_value = get_nested_yaml_value(object_config, "u").unwrap_or(PLANE_U);
+            let v_value = get_nested_yaml_value(object_config, "v").unwrap_or(PLANE_V);
+
+            let q = Vector3::new(q_value[0], q_value[1], q_value[2]);
+            let u = Vector3::new(u_value[0], u_value[1], u_value[2]);
+            let v = Vector3::new(v_value[0], v_value[1], v_value[2]);
+
+            let plane = Plane::new(
+                q,
+                u,
+                v,
+                object_material(materials, object_config)?
             );
 
-            match object_type.as_str() {
-                "Plane" => {
-                    let q_value = get_nested_yaml_value(object_config, "q").unwrap_or([
-                        -6.0, -0.5, -6.0,
-                    ]);
-                    let u_value = get_nested_yaml_value(object_config, "u").unwrap_or([
-                        12.0, 0.0, 0.0,
-                    ]);
-                    let v_value = get_nested_yaml_value(object_config, "v").unwrap_or([
-                        0.0, 0.0, 12.0,
-                    ]);
-
-                    let q = Vector3::new(q_value[0], q_value[1], q_value[2]);
-                    let u = Vector3::new(u_value[0], u_value[1], u_value[2]);
-                    let v = Vector3::new(v_value[0], v_value[1], v_value[2]);
-
-                    let material_name = get_nested_yaml_value(object_config, "mat").unwrap_or(
-                        "".to_string()
-                    );
+            Arc::new(plane)
+        }
+        "Sphere" => {
+            let centre_value = get_nested_yaml_value(object_config, "centre").unwrap_or(
+                SPHERE_CENTRE
+            );
+            let centre = Vector3::new(centre_value[0], centre_value[1], centre_value[2]);
 
-                    let plane = Plane::new(
-                        q,
-                        u,
-                        v,
-                        materials.get(&material_name).expect("could not find material name").clone()
-                    );
+            let radius = get_nested_yaml_value(object_config, "radius").unwrap_or(SPHERE_RADIUS);
 
-                    objects.push(Rc::new(plane));
-                }
-                "Sphere" => {
-                    let centre_value = get_nested_yaml_value(object_config, "centre").unwrap_or([
-                        0.0, 0.0, 0.0,
-                    ]);
-                    let centre = Vector3::new(centre_value[0], centre_value[1], centre_value[2]);
+            let sphere = Sphere::new(
+                centre,
+                radius,
+                object_material(materials, object_config)?
+            );
+            Arc::new(sphere)
+        }
+        "Cuboid" => {
+            let a_value = get_nested_yaml_value(object_config, "a").unwrap_or(CUBOID_A);
+            let b_value = get_nested_yaml_value(object_config, "b").unwrap_or(CUBOID_B);
 
-                    let radius = get_nested_yaml_value(object_config, "radius").unwrap_or(1.0);
+            let a = Vector3::new(a_value[0], a_value[1], a_value[2]);
+            let b = Vector3::new(b_value[0], b_value[1], b_value[2]);
 
-                    let material_name = get_nested_yaml_value(object_config, "mat").unwrap_or(
-                        "".to_string()
-                    );
+            cuboid(a, b, object_material(materials, object_config)?)
+        }
+        "Cylinder" => {
+            let centre_value = get_nested_yaml_value(object_config, "centre").unwrap_or(
+                CYLINDER_CENTRE
+            );
+            let centre = Vector3::new(centre_value[0], centre_value[1], centre_value[2]);
 
-                    let sphere = Sphere::new(
-                        centre,
-                        radius,
-                        materials.get(&material_name).expect("could not find material name").clone()
-                    );
-                    objects.push(Rc::new(sphere));
+            let radius = get_nested_yaml_value(object_config, "radius").unwrap_or(CYLINDER_RADIUS);
+
+            let height = get_nested_yaml_value(object_config, "height").unwrap_or(CYLINDER_HEIGHT);
+
+            let cylinder = Cylinder::new(
+                centre,
+                radius,
+                height,
+                object_material(materials, object_config)?
+            );
+            Arc::new(cylinder)
+        }
+        "Volume" => {
+            let grid_path: String = get_nested_yaml_value(object_config, "grid").ok_or(
+                "volume is missing the 'grid' key"
+            )?;
+            let grid = VoxelGrid::from_file(&grid_path)?;
+
+            let density_scale = get_nested_yaml_value(object_config, "density").unwrap_or(
+                VOLUME_DENSITY
+            );
+            let anisotropy = get_nested_yaml_value(object_config, "anisotropy").unwrap_or(
+                VOLUME_ANISOTROPY
+            );
+            let colour_value = get_nested_yaml_value(object_config, "colour").unwrap_or(
+                VOLUME_COLOUR
+            );
+            let colour = Colour::new(colour_value[0], colour_value[1], colour_value[2]);
+
+            let volume = HeterogeneousMedium::new(
+                grid,
+                density_scale,
+                Arc::new(HenyeyGreenstein::new(colour, anisotropy))
+            );
+            Arc::new(volume)
+        }
+        // Add more object types as needed
+        _ => {
+            return Ok(None);
+        }
+    };
+    Ok(Some(object))
+}
+
+fn object_material(
+    materials: &Materials,
+    object_config: &Value
+) -> Result<Arc<dyn Material>, Box<dyn std::error::Error>> {
+    let name: String = get_nested_yaml_value(object_config, "mat").ok_or(
+        "object is missing the 'mat' key"
+    )?;
+    match materials.get(&name) {
+        Some(material) => Ok(material.clone()),
+        None => Err(format!("unknown material '{}'", name).into()),
+    }
+}
+
+pub fn canonical_materials(materials_value: &Value) -> Value {
+    // The materials as extract_materials reads them, with every default written out.
+    let mut canonical = Vec::new();
+    if let Value::Sequence(materials_seq) = materials_value {
+        for material_entry in materials_seq {
+            let Some((material_name, material_properties)) = material_entry
+                .as_mapping()
+                .and_then(|mapping| mapping.iter().next()) else {
+                continue;
+            };
+            let mut properties = Mapping::new();
+            let mut set = |key: &str, value: Value| {
+                properties.insert(Value::from(key), value);
+            };
+            let number = |key: &str, default: f64| {
+                Value::from(get_nested_yaml_value(material_properties, key).unwrap_or(default))
+            };
+            let triple = |key: &str, default: [f64; 3]| {
+                let value = get_nested_yaml_value(material_properties, key).unwrap_or(default);
+                serde_yaml::to_value(value).unwrap()
+            };
+            let name = |key: &str| {
+                let value: Option<String> = get_nested_yaml_value(material_properties, key);
+                Value::from(value.unwrap_or_default())
+            };
+
+            let material_type: String = get_nested_yaml_value(
+                material_properties,
+                "type"
+            ).unwrap_or(DEFAULT_MATERIAL.to_string());
+            if material_constructor(&material_type).is_some() {
+                // Registered types' settings are only known to their constructors.
+                canonical.push(material_entry.clone());
+                continue;
+            }
+            match material_type.as_str() {
+                "Metal" => {
+                    set("type", Value::from("Metal"));
+                    set("colour", triple("colour", DEFAULT_COLOUR));
                 }
-                "Cuboid" => {
-                    let a_value = get_nested_yaml_value(object_config, "a").unwrap_or([
-                        -1.0, -0.5, -1.0,
-                    ]);
-                    let b_value = get_nested_yaml_value(object_config, "b").unwrap_or([
-                        1.0, 0.5, 1.0,
-                    ]);
-
-                    let a = Vector3::new(a_value[0], a_value[1], a_value[2]);
-                    let b = Vector3::new(b_value[0], b_value[1], b_value[2]);
-
-                    let material_name = get_nested_yaml_value(object_config, "mat").unwrap_or(
-                        "".to_string()
+                "Pbr" | "Principled" => {
+                    let defaults = PbrParameters::default();
+                    set("type", Value::from("Pbr"));
+                    set("colour", triple("colour", DEFAULT_COLOUR));
+                    set("metallic", number("metallic", defaults.metallic));
+                    set("roughness", number("roughness", defaults.roughness));
+                    set("specular", number("specular", defaults.specular));
+                    set("ior", number("ior", defaults.ior));
+                    set("transmission", number("transmission", defaults.transmission));
+                    set("clearcoat", number("clearcoat", defaults.clearcoat));
+                    set(
+                        "clearcoat_roughness",
+                        number("clearcoat_roughness", defaults.clearcoat_roughness)
                     );
-
-                    let cuboid = cuboid(
-                        a,
-                        b,
-                        materials.get(&material_name).expect("could not find material name").clone()
+                    set("sheen", number("sheen", defaults.sheen));
+                }
+                "Mix" => {
+                    set("type", Value::from("Mix"));
+                    set("a", name("a"));
+                    set("b", name("b"));
+                    let factor = get_nested_yaml_value::<String>(material_properties, "factor");
+                    let factor_map: Option<String> = get_nested_yaml_value(
+                        material_properties,
+                        "factor_map"
                     );
-
-                    objects.push(cuboid);
+                    if factor_map.is_some() {
+                        set("factor_map", name("factor_map"));
+                    } else if factor.is_some_and(|f| f == "fresnel") {
+                        set("factor", Value::from("fresnel"));
+                        set("ior", number("ior", MIX_FRESNEL_IOR));
+                    } else {
+                        set("factor", number("factor", MIX_FACTOR));
+                    }
                 }
-                "Cylinder" => {
-                    let centre_value = get_nested_yaml_value(object_config, "centre").unwrap_or([
-                        0.0, 0.5, 0.0,
-                    ]);
-                    let centre = Vector3::new(centre_value[0], centre_value[1], centre_value[2]);
+                "Subsurface" => {
+                    set("type", Value::from("Subsurface"));
+                    set("colour", triple("colour", DEFAULT_COLOUR));
+                    set("mean_free_path", triple("mean_free_path", SUBSURFACE_MEAN_FREE_PATH));
+                    set("ior", number("ior", SUBSURFACE_IOR));
+                    set("anisotropy", number("anisotropy", SUBSURFACE_ANISOTROPY));
+                    let max_steps = get_nested_yaml_value(material_properties, "max_steps");
+                    set("max_steps", Value::from(max_steps.unwrap_or(SUBSURFACE_MAX_STEPS)));
+                }
+                "Coated" => {
+                    set("type", Value::from("Coated"));
+                    set("colour", triple("colour", DEFAULT_COLOUR));
+                    set("base", name("base"));
+                    set("ior", number("ior", COATED_IOR));
+                    set("roughness", number("roughness", COATED_ROUGHNESS));
+                }
+                // Unknown types are rendered as Lambertian.
+                _ => {
+                    set("type", Value::from(DEFAULT_MATERIAL));
+                    set("colour", triple("colour", DEFAULT_COLOUR));
+                }
+            }
 
-                    let radius = get_nested_yaml_value(object_config, "radius").unwrap_or(0.6);
+            if get_nested_yaml_value::<String>(material_properties, "normal_map").is_some() {
+                set("normal_map", name("normal_map"));
+            } else if get_nested_yaml_value::<String>(material_properties, "bump_map").is_some() {
+                set("bump_map", name("bump_map"));
+                set("bump_scale", number("bump_scale", BUMP_SCALE));
+            }
 
-                    let height = get_nested_yaml_value(object_config, "height").unwrap_or(1.0);
+            let mut entry = Mapping::new();
+            entry.insert(material_name.clone(), Value::Mapping(properties));
+            canonical.push(Value::Mapping(entry));
+        }
+    }
+    Value::Sequence(canonical)
+}
 
-                    let material_name = get_nested_yaml_value(object_config, "mat").unwrap_or(
-                        "".to_string()
-                    );
+pub fn canonical_objects(objects_value: &Value) -> Value {
+    // The objects as extract_objects reads them, with every default written out. Objects of
+    // registered types are kept as they are, and objects of unknown types are left out, as they
+    // aren't rendered.
+    let mut canonical = Vec::new();
+    if let Value::Sequence(objects_seq) = objects_value {
+        for object_config in objects_seq {
+            let mut properties = Mapping::new();
+            let mut set = |key: &str, value: Value| {
+                properties.insert(Value::from(key), value);
+            };
+            let number = |key: &str, default: f64| {
+                Value::from(get_nested_yaml_value(object_config, key).unwrap_or(default))
+            };
+            let triple = |key: &str, default: [f64; 3]| {
+                let value = get_nested_yaml_value(object_config, key).unwrap_or(default);
+                serde_yaml::to_value(value).unwrap()
+            };
+            let text = |key: &str| {
+                let value: Option<String> = get_nested_yaml_value(object_config, key);
+                Value::from(value.unwrap_or_default())
+            };
 
-                    let cylinder = Cylinder::new(
-                        centre,
-                        radius,
-                        height,
-                        materials.get(&material_name).expect("could not find material name").clone()
-                    );
-                    objects.push(Rc::new(cylinder));
+            if let Some(name) = get_nested_yaml_value::<String>(object_config, "name") {
+                set("name", Value::from(name));
+            }
+            let object_type: String = get_nested_yaml_value(object_config, "type")
+                .unwrap_or_default();
+            if object_constructor(&object_type).is_some() {
+                canonical.push(object_config.clone());
+                continue;
+            }
+            match object_type.as_str() {
+                "Plane" => {
+                    set("type", Value::from("Plane"));
+                    set("q", triple("q", PLANE_Q));
+                    set("u", triple("u", PLANE_U));
+                    set("v", triple("v", PLANE_V));
+                    set("mat", text("mat"));
+                }
+                "Sphere" => {
+                    set("type", Value::from("Sphere"));
+                    set("centre", triple("centre", SPHERE_CENTRE));
+                    set("radius", number("radius", SPHERE_RADIUS));
+                    set("mat", text("mat"));
+                }
+                "Cuboid" => {
+                    set("type", Value::from("Cuboid"));
+                    set("a", triple("a", CUBOID_A));
+                    set("b", triple("b", CUBOID_B));
+                    set("mat", text("mat"));
+                }
+                "Cylinder" => {
+                    set("type", Value::from("Cylinder"));
+                    set("centre", triple("centre", CYLINDER_CENTRE));
+                    set("radius", number("radius", CYLINDER_RADIUS));
+                    set("height", number("height", CYLINDER_HEIGHT));
+                    set("mat", text("mat"));
+                }
+                "Volume" => {
+                    set("type", Value::from("Volume"));
+                    set("grid", text("grid"));
+                    set("density", number("density", VOLUME_DENSITY));
+                    set("anisotropy", number("anisotropy", VOLUME_ANISOTROPY));
+                    set("colour", triple("colour", VOLUME_COLOUR));
                 }
-                // Add more object types as needed
                 _ => {
-                    // Handle unknown object types or log an error
-                    eprintln!("Unknown object type: {}", object_type);
+                    continue;
                 }
             }
+            if let Some(angle) = get_nested_yaml_value::<f64>(object_config, "rotate_y") {
+                set("rotate_y", Value::from(angle));
+            }
+            if let Some(offset) = get_nested_yaml_value::<[f64; 3]>(object_config, "translate") {
+                set("translate", serde_yaml::to_value(offset).unwrap());
+            }
+            canonical.push(Value::Mapping(properties));
         }
     }
+    Value::Sequence(canonical)
+}
 
-    Ok(objects)
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    // Parses durations such as "45", "90s", "10m" or "1h30m". A bare number is in seconds.
+    let invalid = || format!("invalid duration '{}'", text);
+    if text.is_empty() {
+        return Err(invalid());
+    }
+
+    // Infinite, NaN and too-long durations don't fit in a Duration.
+    let to_duration = |seconds: f64| {
+        if !seconds.is_finite() {
+            return Err(invalid());
+        }
+        Duration::try_from_secs_f64(seconds.max(0.0)).map_err(|_| invalid())
+    };
+
+    if let Ok(seconds) = text.parse::<f64>() {
+        return to_duration(seconds);
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let value: f64 = number.parse().map_err(|_| invalid())?;
+        total += match c {
+            'h' => value * 3600.0,
+            'm' => value * 60.0,
+            's' => value,
+            _ => {
+                return Err(invalid());
+            }
+        };
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+
+    to_duration(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for text in ["", "m", "10x", "10m5", "1..5s"] {
+            assert!(parse_duration(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_durations_that_do_not_fit() {
+        for text in ["inf", "nan", "1e30", "99999999999999999999h"] {
+            assert!(parse_duration(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn canonical_scenes_write_out_defaults() {
+        let yaml = |text: &str| serde_yaml::from_str::<Value>(text).unwrap();
+        assert_eq!(
+            canonical_materials(&yaml("[shell: { type: Coated, base: core }, odd: { type: Odd }]")),
+            yaml(
+                "[shell: { type: Coated, colour: [1.0, 1.0, 1.0], base: core, ior: 1.5,
+                           roughness: 0.0 },
+                  odd: { type: Lambertian, colour: [1.0, 1.0, 1.0] }]"
+            )
+        );
+        assert_eq!(
+            canonical_objects(&yaml("[{ type: Cylinder, mat: shell }, { type: Odd }]")),
+            yaml(
+                "[{ type: Cylinder, centre: [0.0, 0.5, 0.0], radius: 0.6, height: 1.0,
+                    mat: shell }]"
+            )
+        );
+    }
 }
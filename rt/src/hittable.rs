@@ -1,13 +1,14 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::Ray;
+use crate::ray::Ray;
 use crate::aabb::Aabb;
 use crate::colour::Colour;
 use crate::material::{ Material, Lambertian };
 use crate::vector3::{ Point3, Vector3, dot };
 use crate::interval::Interval;
+use crate::rtweekend::degrees_to_radians;
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
 
     fn bounding_box(&self) -> Aabb;
@@ -17,26 +18,36 @@ pub trait Hittable {
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vector3,
-    pub mat: Rc<dyn Material>,
+    pub dpdu: Vector3, // Surface tangent along increasing u
+    pub dpdv: Vector3, // Surface tangent along increasing v
+    pub mat: Arc<dyn Material>,
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    pub object_id: i32, // Position of the hit object in the scene, counting from 1
+    pub material_id: i32, // Position of the hit object's material in the scene, counting from 1
 }
 
-impl HitRecord {
-    pub fn default() -> Self {
+impl Default for HitRecord {
+    fn default() -> Self {
         HitRecord {
             p: Point3::default(),
             normal: Vector3::default(),
-            mat: Rc::new(Lambertian::new(Colour::default())),
+            dpdu: Vector3::default(),
+            dpdv: Vector3::default(),
+            mat: Arc::new(Lambertian::new(Colour::default())),
             t: 0.0,
             front_face: false,
             u: 0.0,
             v: 0.0,
+            object_id: 0,
+            material_id: 0,
         }
     }
+}
 
+impl HitRecord {
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vector3) {
         // Sets the hit record normal vector.
         // NOTE: the parameter `outward_normal` is assumed to have unit length.
@@ -45,3 +56,136 @@ impl HitRecord {
         self.normal = if self.front_face { *outward_normal } else { -*outward_normal };
     }
 }
+
+// Wraps an object to label its hits with the object's and its material's IDs.
+pub struct Identified {
+    object: Arc<dyn Hittable>,
+    object_id: i32,
+    material_id: i32,
+}
+
+impl Identified {
+    pub fn new(object: Arc<dyn Hittable>, object_id: i32, material_id: i32) -> Self {
+        Identified { object, object_id, material_id }
+    }
+}
+
+impl Hittable for Identified {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.object.hit(r, ray_t, rec) {
+            return false;
+        }
+        rec.object_id = self.object_id;
+        rec.material_id = self.material_id;
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+}
+
+// Moves an object by an offset.
+pub struct Translate {
+    object: Arc<dyn Hittable>,
+    offset: Vector3,
+    bbox: Aabb,
+}
+
+impl Translate {
+    pub fn new(object: Arc<dyn Hittable>, offset: Vector3) -> Self {
+        let bbox = object.bounding_box();
+        let bbox = Aabb::new(
+            Interval::new(bbox.x.min + offset.x(), bbox.x.max + offset.x()),
+            Interval::new(bbox.y.min + offset.y(), bbox.y.max + offset.y()),
+            Interval::new(bbox.z.min + offset.z(), bbox.z.max + offset.z())
+        );
+        Translate { object, offset, bbox }
+    }
+}
+
+impl Hittable for Translate {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Move the ray backwards by the offset, find an intersection with the object, and move
+        // the intersection point forwards by the offset.
+        let offset_r = Ray::new(r.origin() - self.offset, r.direction());
+        if !self.object.hit(&offset_r, ray_t, rec) {
+            return false;
+        }
+        rec.p += self.offset;
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+// Rotates an object about the y axis.
+pub struct RotateY {
+    object: Arc<dyn Hittable>,
+    sin_theta: f64,
+    cos_theta: f64,
+    bbox: Aabb,
+}
+
+impl RotateY {
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> Self {
+        let radians = degrees_to_radians(angle);
+        let sin_theta = radians.sin();
+        let cos_theta = radians.cos();
+        let bbox = object.bounding_box();
+
+        // The bounding box of the rotated corners of the object's box.
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for x in [bbox.x.min, bbox.x.max] {
+            for y in [bbox.y.min, bbox.y.max] {
+                for z in [bbox.z.min, bbox.z.max] {
+                    let new_x = cos_theta * x + sin_theta * z;
+                    let new_z = -sin_theta * x + cos_theta * z;
+                    min = Point3::new(min.x().min(new_x), min.y().min(y), min.z().min(new_z));
+                    max = Point3::new(max.x().max(new_x), max.y().max(y), max.z().max(new_z));
+                }
+            }
+        }
+
+        RotateY { object, sin_theta, cos_theta, bbox: Aabb::from_points(min, max) }
+    }
+
+    fn to_object(&self, v: Vector3) -> Vector3 {
+        Vector3::new(
+            self.cos_theta * v.x() - self.sin_theta * v.z(),
+            v.y(),
+            self.sin_theta * v.x() + self.cos_theta * v.z()
+        )
+    }
+
+    fn to_world(&self, v: Vector3) -> Vector3 {
+        Vector3::new(
+            self.cos_theta * v.x() + self.sin_theta * v.z(),
+            v.y(),
+            -self.sin_theta * v.x() + self.cos_theta * v.z()
+        )
+    }
+}
+
+impl Hittable for RotateY {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Transform the ray into object space, find an intersection there, and transform the
+        // intersection back into world space.
+        let rotated_r = Ray::new(self.to_object(r.origin()), self.to_object(r.direction()));
+        if !self.object.hit(&rotated_r, ray_t, rec) {
+            return false;
+        }
+        rec.p = self.to_world(rec.p);
+        rec.normal = self.to_world(rec.normal);
+        rec.dpdu = self.to_world(rec.dpdu);
+        rec.dpdv = self.to_world(rec.dpdv);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
@@ -1,18 +1,18 @@
-use std::{ rc::Rc };
+use std::{ sync::Arc };
 
 use crate::{ hittable::{ Hittable, HitRecord }, interval::Interval, aabb::Aabb };
 
 pub struct HittableList {
-    pub objects: Vec<Rc<dyn Hittable>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
     bbox: Aabb,
 }
 
-impl<'a> HittableList {
+impl HittableList {
     pub fn new() -> Self {
         HittableList { objects: vec![], bbox: Aabb::default() }
     }
 
-    // pub fn from(object: Rc<dyn Hittable>) -> Self {
+    // pub fn from(object: Arc<dyn Hittable>) -> Self {
     //     let bbox = Aabb::from_boxes(Aabb::default(), object.bounding_box());
     //     HittableList { objects: [object].to_vec(), bbox }
     // }
@@ -21,28 +21,34 @@ impl<'a> HittableList {
     //     self.objects.clear();
     // }
 
-    pub fn add(&mut self, object: Rc<dyn Hittable>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.bbox = Aabb::from_boxes(self.bbox, object.bounding_box());
         self.objects.push(object);
     }
 }
 
-impl<'a> Hittable for HittableList {
-    fn hit(&self, r: &crate::ray::Ray, ray_t: Interval, mut rec: &mut HitRecord) -> bool {
+impl Default for HittableList {
+    fn default() -> Self {
+        HittableList::new()
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: &crate::ray::Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
 
         for object in &self.objects {
-            if object.hit(r, Interval::new(ray_t.min, closest_so_far), &mut rec) {
+            if object.hit(r, Interval::new(ray_t.min, closest_so_far), rec) {
                 hit_anything = true;
                 closest_so_far = rec.t;
             }
         }
 
-        return hit_anything;
+        hit_anything
     }
 
     fn bounding_box(&self) -> Aabb {
-        return self.bbox;
+        self.bbox
     }
 }
@@ -0,0 +1,109 @@
+use std::fs;
+
+use crate::colour::Colour;
+
+// An RGB image read from a PPM file, used for texture-like lookups such as normal and bump maps.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Colour>, // Rows from top to bottom, with components in [0,1]
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Colour>) -> Self {
+        // An image made in code, with rows from top to bottom.
+        assert_eq!(pixels.len(), width * height, "an image needs a colour for every pixel");
+        Image { width, height, pixels }
+    }
+
+    pub fn load_ppm(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        // Supports both the ASCII (P3) and binary (P6) PPM variants with 8-bit samples.
+        let bytes = fs::read(path)?;
+
+        // Read the four whitespace-separated header fields, skipping '#' comments.
+        let mut fields: Vec<String> = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 && pos < bytes.len() {
+            if bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else if bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            } else {
+                let start = pos;
+                while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                fields.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+            }
+        }
+        if fields.len() < 4 {
+            return Err(format!("'{}' has an incomplete PPM header", path).into());
+        }
+
+        let width: usize = fields[1].parse()?;
+        let height: usize = fields[2].parse()?;
+        let max_value: f64 = fields[3].parse()?;
+        let count = width * height * 3;
+
+        let samples: Vec<f64> = match fields[0].as_str() {
+            "P3" => {
+                let body = std::str::from_utf8(&bytes[pos..])?;
+                body.split_whitespace()
+                    .take(count)
+                    .map(|s| s.parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()?
+            }
+            "P6" => {
+                // A single whitespace byte separates the header from the binary samples.
+                let body = bytes.get(pos + 1..).unwrap_or(&[]);
+                body.iter()
+                    .take(count)
+                    .map(|&b| b as f64)
+                    .collect()
+            }
+            other => {
+                return Err(format!("'{}' has unsupported PPM format '{}'", path, other).into());
+            }
+        };
+        if samples.len() != count {
+            return Err(format!("'{}' is missing pixel data", path).into());
+        }
+
+        let pixels = samples
+            .chunks_exact(3)
+            .map(|c| Colour::new(c[0] / max_value, c[1] / max_value, c[2] / max_value))
+            .collect();
+
+        Ok(Image { width, height, pixels })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn sample(&self, u: f64, v: f64) -> Colour {
+        // Bilinearly filtered lookup, wrapping around at the edges. v = 0 is the bottom row.
+        let x = u * (self.width as f64) - 0.5;
+        let y = (1.0 - v) * (self.height as f64) - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let texel = |i: f64, j: f64| -> Colour {
+            let i = (i as i64).rem_euclid(self.width as i64) as usize;
+            let j = (j as i64).rem_euclid(self.height as i64) as usize;
+            self.pixels[j * self.width + i]
+        };
+
+        let top = (1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1.0, y0);
+        let bottom = (1.0 - fx) * texel(x0, y0 + 1.0) + fx * texel(x0 + 1.0, y0 + 1.0);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
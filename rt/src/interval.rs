@@ -1,5 +1,3 @@
-use std::f64::{ INFINITY, NEG_INFINITY };
-
 #[derive(Debug, Copy, Clone)]
 pub struct Interval {
     pub min: f64,
@@ -16,7 +14,7 @@ impl Interval {
     }
 
     pub fn empty() -> Self {
-        Self { min: INFINITY, max: NEG_INFINITY }
+        Self { min: f64::INFINITY, max: f64::NEG_INFINITY }
     }
 
     // pub fn universe() -> Self {
@@ -38,16 +36,16 @@ impl Interval {
         if x > self.max {
             return self.max;
         }
-        return x;
+        x
     }
 
     pub fn size(&self) -> f64 {
-        return self.max - self.min;
+        self.max - self.min
     }
 
     pub fn expand(&self, delta: f64) -> Interval {
         let padding = delta / 2.0;
-        return Interval::new(self.min - padding, self.max + padding);
+        Interval::new(self.min - padding, self.max + padding)
     }
 }
 
@@ -0,0 +1,74 @@
+// The renderer as a library. Scenes can be read from scene files or built in code, and rendered
+// into a framebuffer in memory:
+//
+//     let mut scene = Scene::default();
+//     let ground = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+//     scene.add(Arc::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, ground)));
+//     let framebuffer = render(&scene, &RenderSettings::default())?;
+//
+// The items re-exported here are the stable API. The modules behind them are public for the
+// command line tool and for anything the re-exports don't cover, but may change.
+
+pub mod vector3;
+pub mod colour;
+pub mod ray;
+pub mod hittable;
+pub mod sphere;
+pub mod hittable_list;
+pub mod rtweekend;
+pub mod interval;
+pub mod camera;
+pub mod material;
+pub mod quad;
+pub mod aabb;
+mod bvh;
+pub mod cylinder;
+pub mod helper;
+pub mod volume;
+pub mod image;
+pub mod film;
+pub mod checkpoint;
+pub mod sampler;
+pub mod filter;
+pub mod aov;
+mod exr;
+mod denoise;
+pub mod tile;
+pub mod distributed;
+pub mod animation;
+pub mod scene_file;
+pub mod generate;
+pub mod stats;
+pub mod progress;
+pub mod scene;
+pub mod registry;
+
+pub use camera::Camera;
+pub use colour::Colour;
+pub use cylinder::Cylinder;
+pub use film::{ Framebuffer, Region };
+pub use hittable::{ Hittable, RotateY, Translate };
+pub use hittable_list::HittableList;
+pub use image::Image;
+pub use material::{
+    Coated,
+    HenyeyGreenstein,
+    Lambertian,
+    Material,
+    Metal,
+    Mix,
+    MixFactor,
+    NormalPerturbation,
+    Pbr,
+    PbrParameters,
+    Perturbed,
+    Subsurface,
+};
+pub use progress::ProgressStyle;
+pub use quad::{ cuboid, Plane };
+pub use registry::{ register_material, register_object };
+pub use scene::{ parse_scene, render, RenderSettings, Scene };
+pub use sphere::Sphere;
+pub use stats::RenderStats;
+pub use vector3::{ Point3, Vector3 };
+pub use volume::{ HeterogeneousMedium, VoxelGrid };
@@ -1,74 +1,144 @@
-mod vector3;
-mod colour;
-mod ray;
-mod hittable;
-mod sphere;
-mod hittable_list;
-mod rtweekend;
-mod interval;
-mod camera;
-mod material;
-mod quad;
-mod aabb;
-mod bvh;
-mod cylinder;
-mod helper;
+mod cli;
 
-use std::collections::HashMap;
+use std::time::{ Duration, Instant };
 
-use camera::Camera;
-
-use helper::{ extract_materials, extract_objects, get_nested_yaml_value };
+use rt::{
+    animation::Animation,
+    checkpoint::Checkpoint,
+    distributed,
+    generate,
+    progress::Progress,
+    scene_file,
+    Camera,
+    Scene,
+};
 use serde_yaml::Value;
 
-use crate::hittable_list::HittableList;
-use crate::ray::Ray;
+use cli::{ Command, Options };
 
 fn main() {
-    // World
-    let mut world = HittableList::new();
-
-    let yaml_content = std::fs
-        ::read_to_string("/Users/harrygardiner/rt-01F/rt/config.yaml")
-        .unwrap();
-    let yaml_value: Value = serde_yaml::from_str(&yaml_content).unwrap();
-    let mut materials = HashMap::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match cli::parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
 
-    // Extract materials configuration
-    if let Some(materials_value) = yaml_value.get("materials") {
-        materials = extract_materials(materials_value).unwrap();
-    } else {
-        eprintln!("'materials' key not found in the YAML file");
-    }
+    match &options.command {
+        Command::Render => {
+            let parse_start = Instant::now();
+            let result = scene_file::load(&options.scene).and_then(|yaml_value| {
+                match Animation::from_scene(&yaml_value)? {
+                    Some(animation) => {
+                        let load_time = parse_start.elapsed();
+                        render_animation(&animation, &yaml_value, &options, load_time)
+                    }
+                    None => {
+                        let yaml_content = serde_yaml::to_string(&yaml_value)?;
+                        let parse_time = parse_start.elapsed();
+                        let build_start = Instant::now();
+                        let mut scene = Scene::from_yaml_str(&options.scene, &yaml_content)?;
+                        scene.camera.set_load_times(parse_time, build_start.elapsed());
+                        apply_options(&mut scene.camera, &options);
+                        scene.camera.render(&scene.world, scene.filename)
+                    }
+                }
+            });
+            if let Err(err) = result {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Command::Resume(checkpoint_path) => {
+            let (checkpoint, film) = match Checkpoint::load(checkpoint_path) {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            };
 
-    if let Some(object_values) = yaml_value.get("objects") {
-        let objects = extract_objects(object_values, &materials).unwrap();
+            let mut scene = match Scene::load(&checkpoint.scene_path) {
+                Ok(scene) => scene,
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            apply_options(&mut scene.camera, &options);
+            scene.camera.checkpoint = Some(checkpoint_path.clone());
 
-        for obj in objects {
-            world.add(obj);
+            if let Err(err) = scene.camera.resume(&scene.world, scene.filename, checkpoint, film) {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
         }
-    }
-
-    // Camera
-    let mut cam: Camera = match
-        Camera::from_yaml_file("/Users/harrygardiner/rt-01F/rt/config.yaml")
-    {
-        Ok(camera) => {
-            // Use the camera instance as needed
-            //println!("{:?}", camera);
-            camera
+        Command::Worker(address) => {
+            if let Err(err) = distributed::serve(address) {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
         }
-        Err(err) => {
-            eprintln!("Error: {}", err);
-            Camera::new()
+        Command::Convert(output_path) => {
+            let result = scene_file::load(&options.scene).and_then(|yaml_value| {
+                let canonical = scene_file::canonical(&options.scene, &yaml_value)?;
+                scene_file::save(output_path, &canonical)
+            });
+            match result {
+                Ok(()) => println!("Wrote {}", output_path),
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            }
         }
-    };
+        Command::Generate(name) => {
+            // The scene goes to standard output, to be redirected to a file.
+            let result = generate::Generator::from_name(name).and_then(|generator| {
+                generate::generate(generator, options.count, options.settings.seed.unwrap_or(1))
+            });
+            match result {
+                Ok(scene) => print!("{}", serde_yaml::to_string(&scene).unwrap()),
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
 
-    let filename_value: Option<String> = get_nested_yaml_value(&yaml_value, "filename");
+fn render_animation(
+    animation: &Animation,
+    yaml_value: &Value,
+    options: &Options,
+    load_time: Duration
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Render each frame of the scene with its animated settings applied, numbering the images
+    // after the frame. The first frame's statistics include loading the scene file.
+    let (first, last) = options.frames.unwrap_or((animation.first_frame, animation.last_frame));
+    let mut load_time = load_time;
+    let progress = Progress::new(options.settings.progress);
+    for frame in first..=last {
+        let time = ((frame - animation.first_frame) as f64) / animation.fps;
+        progress.frame(frame, first, last, time);
+        let parse_start = Instant::now();
+        let frame_yaml = serde_yaml::to_string(&animation.scene_at(yaml_value, frame)?)?;
+        let parse_time = load_time + parse_start.elapsed();
+        load_time = Duration::ZERO;
+        let build_start = Instant::now();
+        let mut scene = Scene::from_yaml_str(&options.scene, &frame_yaml)?;
+        scene.camera.set_load_times(parse_time, build_start.elapsed());
+        apply_options(&mut scene.camera, options);
+        scene.camera.render(&scene.world, format!("{}_{:04}", scene.filename, frame))?;
+    }
+    Ok(())
+}
 
-    let filename = match filename_value {
-        Some(name) => name,
-        None => "output_image".to_string(), // Provide a default filename if it's not present
-    };
-    cam.render(&world, filename)
+fn apply_options(cam: &mut Camera, options: &Options) {
+    // Command line options take precedence over the scene file.
+    options.settings.apply(cam);
+    cam.debug_pixel = options.debug_pixel;
 }
@@ -1,18 +1,49 @@
+use std::{ f64::consts::PI, sync::Arc };
+
 use crate::{
     ray::Ray,
     hittable::HitRecord,
     colour::Colour,
-    vector3::{ random_unit_vector, reflect, unit_vector },
+    image::Image,
+    sampler::Sampler,
+    vector3::{
+        Vector3,
+        sample_unit_vector,
+        reflect,
+        refract,
+        unit_vector,
+        orthonormal_basis,
+        dot,
+        cross,
+    },
 };
 
-pub trait Material {
+pub trait Material: Send + Sync {
     fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Colour,
-        scattered: &mut Ray
-    ) -> bool;
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe>;
+
+    // The material's overall colour for the denoiser and the albedo AOV.
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Colour;
+
+    // For materials that random-walk light inside them, the most scattering events a path may
+    // have inside them. Those count towards this limit rather than the camera's max_depth.
+    fn max_walk_steps(&self) -> Option<i32> {
+        None
+    }
+}
+
+// The kind of scattering a material chose, so renders can be split into diffuse and specular
+// light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
 }
 
 pub struct Lambertian {
@@ -26,14 +57,19 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
+    fn albedo(&self, _: &Ray, _: &HitRecord) -> Colour {
+        self.albedo
+    }
+
     fn scatter(
         &self,
         _: &Ray,
         rec: &HitRecord,
         attenuation: &mut Colour,
-        scattered: &mut Ray
-    ) -> bool {
-        let mut scatter_direction = rec.normal + random_unit_vector();
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe> {
+        let mut scatter_direction = rec.normal + sample_unit_vector(sampler.get_2d());
 
         // Catch degenerate scatter direction
         if scatter_direction.near_zero() {
@@ -42,7 +78,7 @@ impl Material for Lambertian {
 
         *scattered = Ray::new(rec.p, scatter_direction);
         *attenuation = self.albedo;
-        return true;
+        Some(Lobe::Diffuse)
     }
 }
 
@@ -57,16 +93,743 @@ impl Metal {
 }
 
 impl Material for Metal {
+    fn albedo(&self, _: &Ray, _: &HitRecord) -> Colour {
+        self.albedo
+    }
+
     fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Colour,
-        scattered: &mut Ray
-    ) -> bool {
+        scattered: &mut Ray,
+        _: &mut dyn Sampler
+    ) -> Option<Lobe> {
         let reflected = reflect(unit_vector(r_in.direction()), rec.normal);
         *scattered = Ray::new(rec.p, reflected);
         *attenuation = self.albedo;
-        return true;
+        Some(Lobe::Specular)
+    }
+}
+
+pub struct HenyeyGreenstein {
+    albedo: Colour,
+    g: f64, // Anisotropy: -1 is full back-scattering, 0 is isotropic, 1 is full forward-scattering
+}
+
+impl HenyeyGreenstein {
+    pub fn new(a: Colour, g: f64) -> Self {
+        // Keep g away from +/-1, where the phase function degenerates into a delta.
+        HenyeyGreenstein { albedo: a, g: g.clamp(-0.99, 0.99) }
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn albedo(&self, _: &Ray, _: &HitRecord) -> Colour {
+        self.albedo
+    }
+
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Colour,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe> {
+        let forward = unit_vector(r_in.direction());
+        let direction = sample_henyey_greenstein(forward, self.g, sampler.get_2d());
+        *scattered = Ray::new(rec.p, direction);
+        *attenuation = self.albedo;
+        Some(Lobe::Diffuse)
+    }
+}
+
+fn sample_henyey_greenstein(forward: Vector3, g: f64, u: (f64, f64)) -> Vector3 {
+    // Sample the cosine of the angle to the incoming direction by inverting the HG CDF.
+    let xi = u.0;
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * xi
+    } else {
+        let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+
+    let (s, t) = orthonormal_basis(forward);
+    sin_theta * phi.cos() * s + sin_theta * phi.sin() * t + cos_theta * forward
+}
+
+// Parameters of the principled material. Colours and weights follow the conventions of the
+// Disney/Blender principled BSDFs, so values can be copied over from other tools.
+pub struct PbrParameters {
+    pub base_colour: Colour,
+    pub metallic: f64, // 0 is dielectric, 1 is conductor
+    pub roughness: f64, // Perceptual roughness; the GGX alpha is roughness squared
+    pub specular: f64, // Scales dielectric reflectance; 0.5 leaves the IOR's Fresnel unchanged
+    pub ior: f64,
+    pub transmission: f64, // Fraction of refracted light that passes through instead of diffusing
+    pub clearcoat: f64,
+    pub clearcoat_roughness: f64,
+    pub sheen: f64,
+}
+
+impl Default for PbrParameters {
+    fn default() -> Self {
+        PbrParameters {
+            base_colour: Colour::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            ior: 1.5,
+            transmission: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+        }
+    }
+}
+
+pub struct Pbr {
+    params: PbrParameters,
+}
+
+impl Pbr {
+    pub fn new(params: PbrParameters) -> Self {
+        Pbr { params }
+    }
+}
+
+impl Material for Pbr {
+    fn albedo(&self, _: &Ray, _: &HitRecord) -> Colour {
+        self.params.base_colour
+    }
+
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Colour,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe> {
+        // Each call picks one lobe stochastically (clear coat, metal, dielectric reflection,
+        // transmission, sheen or diffuse) with a probability equal to its weight, so the attenuation
+        // only carries the part of the BSDF that is not already accounted for by that choice.
+        let p = &self.params;
+
+        let n = rec.normal;
+        let (s, t) = orthonormal_basis(n);
+        let to_local = |v: Vector3| Vector3::new(dot(v, s), dot(v, t), dot(v, n));
+        let to_world = |v: Vector3| v.x() * s + v.y() * t + v.z() * n;
+
+        let wo = to_local(-unit_vector(r_in.direction()));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        // Clear coat: a colourless, IOR 1.5 layer over the rest of the material.
+        if p.clearcoat > 0.0 {
+            let coat_reflectance = p.clearcoat * schlick(0.04, wo.z());
+            if sampler.get_1d() < coat_reflectance {
+                let alpha = roughness_to_alpha(p.clearcoat_roughness);
+                let h = sample_ggx_vndf(wo, alpha, sampler.get_2d());
+                let wi = reflect(-wo, h);
+                if wi.z() <= 0.0 {
+                    return None;
+                }
+                let g = smith_g2_over_g1(wo, wi, alpha);
+                *attenuation = Colour::new(g, g, g);
+                *scattered = Ray::new(rec.p, to_world(wi));
+                return Some(Lobe::Specular);
+            }
+        }
+
+        let alpha = roughness_to_alpha(p.roughness);
+        let h = sample_ggx_vndf(wo, alpha, sampler.get_2d());
+        let cos_oh = dot(wo, h);
+
+        // Metal: coloured specular reflection only.
+        if sampler.get_1d() < p.metallic {
+            let wi = reflect(-wo, h);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let fresnel = Colour::new(
+                schlick(p.base_colour.x(), cos_oh),
+                schlick(p.base_colour.y(), cos_oh),
+                schlick(p.base_colour.z(), cos_oh)
+            );
+            *attenuation = smith_g2_over_g1(wo, wi, alpha) * fresnel;
+            *scattered = Ray::new(rec.p, to_world(wi));
+            return Some(Lobe::Specular);
+        }
+
+        // Dielectric: uncoloured specular reflection over either transmission or diffuse.
+        // Under total internal reflection no light can refract, however low specular is.
+        let eta = if rec.front_face { 1.0 / p.ior } else { p.ior };
+        let total_internal_reflection = eta * eta * (1.0 - cos_oh * cos_oh) >= 1.0;
+        let reflectance = if total_internal_reflection {
+            1.0
+        } else {
+            ((p.specular / 0.5) * fresnel_dielectric(cos_oh, eta)).min(1.0)
+        };
+        if sampler.get_1d() < reflectance {
+            let wi = reflect(-wo, h);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let g = smith_g2_over_g1(wo, wi, alpha);
+            *attenuation = Colour::new(g, g, g);
+            *scattered = Ray::new(rec.p, to_world(wi));
+            return Some(Lobe::Specular);
+        }
+
+        if sampler.get_1d() < p.transmission {
+            let wi = unit_vector(refract(-wo, h, eta));
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            let g = smith_g2_over_g1(wo, -wi, alpha);
+            *attenuation = g * p.base_colour;
+            *scattered = Ray::new(rec.p, to_world(wi));
+            return Some(Lobe::Specular);
+        }
+
+        // Sheen: a white rim at grazing angles layered over the diffuse lobe. Its reflectance is
+        // the chance of taking it, so together the two never reflect more light than arrives.
+        let sheen = p.sheen > 0.0 &&
+            sampler.get_1d() < p.sheen.min(1.0) * (1.0 - wo.z()).max(0.0).powi(5);
+
+        let mut wi = to_local(rec.normal + sample_unit_vector(sampler.get_2d()));
+        if wi.near_zero() {
+            wi = Vector3::new(0.0, 0.0, 1.0);
+        }
+        let wi = unit_vector(wi);
+
+        *attenuation = if sheen { Colour::new(1.0, 1.0, 1.0) } else { p.base_colour };
+        *scattered = Ray::new(rec.p, to_world(wi));
+        Some(Lobe::Diffuse)
+    }
+}
+
+pub enum MixFactor {
+    Constant(f64),
+    Fresnel(f64), // Blend towards the second material by the dielectric Fresnel term of this IOR
+    Texture(Image), // Blend by the image's brightness at the hit's surface coordinates
+}
+
+pub struct Mix {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    factor: MixFactor,
+}
+
+impl Mix {
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, factor: MixFactor) -> Self {
+        Mix { a, b, factor }
+    }
+
+    fn factor(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        match &self.factor {
+            MixFactor::Constant(f) => *f,
+            MixFactor::Fresnel(ior) => {
+                let cos_theta = dot(-unit_vector(r_in.direction()), rec.normal).clamp(0.0, 1.0);
+                fresnel_dielectric(cos_theta, 1.0 / ior)
+            }
+            MixFactor::Texture(image) => {
+                let c = image.sample(rec.u, rec.v);
+                (c.x() + c.y() + c.z()) / 3.0
+            }
+        }
+    }
+}
+
+impl Material for Mix {
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Colour {
+        let factor = self.factor(r_in, rec);
+        (1.0 - factor) * self.a.albedo(r_in, rec) + factor * self.b.albedo(r_in, rec)
+    }
+
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Colour,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe> {
+        // Choosing one of the two materials with probability equal to its blend weight gives
+        // the blended BSDF on average, without either material needing to evaluate the other.
+        if sampler.get_1d() < self.factor(r_in, rec) {
+            return self.b.scatter(r_in, rec, attenuation, scattered, sampler);
+        }
+        self.a.scatter(r_in, rec, attenuation, scattered, sampler)
+    }
+}
+
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ior: f64,
+    roughness: f64,
+    tint: Colour, // Absorption of the coat, applied to light that reaches the base
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, ior: f64, roughness: f64, tint: Colour) -> Self {
+        Coated { base, ior, roughness, tint }
+    }
+}
+
+impl Material for Coated {
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Colour {
+        self.tint * self.base.albedo(r_in, rec)
+    }
+
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Colour,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe> {
+        // Reflect off the coat with probability equal to its Fresnel reflectance, otherwise
+        // let the base material scatter the light and tint it on its way through the coat.
+        let n = rec.normal;
+        let (s, t) = orthonormal_basis(n);
+        let wo_world = -unit_vector(r_in.direction());
+        let wo = Vector3::new(dot(wo_world, s), dot(wo_world, t), dot(wo_world, n));
+        if wo.z() <= 0.0 {
+            return self.base.scatter(r_in, rec, attenuation, scattered, sampler);
+        }
+
+        let alpha = roughness_to_alpha(self.roughness);
+        let h = sample_ggx_vndf(wo, alpha, sampler.get_2d());
+        if sampler.get_1d() < fresnel_dielectric(dot(wo, h), 1.0 / self.ior) {
+            let wi = reflect(-wo, h);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let g = smith_g2_over_g1(wo, wi, alpha);
+            *attenuation = Colour::new(g, g, g);
+            *scattered = Ray::new(rec.p, wi.x() * s + wi.y() * t + wi.z() * n);
+            return Some(Lobe::Specular);
+        }
+
+        let lobe = self.base.scatter(r_in, rec, attenuation, scattered, sampler)?;
+        *attenuation = self.tint * *attenuation;
+        Some(lobe)
+    }
+}
+
+fn roughness_to_alpha(roughness: f64) -> f64 {
+    // Clamp away from zero so perfectly smooth surfaces don't produce a degenerate distribution.
+    (roughness * roughness).max(1e-3)
+}
+
+fn schlick(f0: f64, cosine: f64) -> f64 {
+    f0 + (1.0 - f0) * (1.0 - cosine).max(0.0).powi(5)
+}
+
+fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    // Unpolarised Fresnel reflectance for light crossing an interface with relative IOR 1/eta.
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0; // Total internal reflection
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_perp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (r_parallel * r_parallel + r_perp * r_perp)
+}
+
+fn smith_lambda(w: Vector3, alpha: f64) -> f64 {
+    let cos2 = w.z() * w.z();
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * (-1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+fn smith_g2_over_g1(wo: Vector3, wi: Vector3, alpha: f64) -> f64 {
+    // The height-correlated masking-shadowing term divided by the masking term, which is the
+    // full GGX sample weight when microfacet normals are drawn from the visible normals.
+    let lambda_o = smith_lambda(wo, alpha);
+    let lambda_i = smith_lambda(wi, alpha);
+    (1.0 + lambda_o) / (1.0 + lambda_o + lambda_i)
+}
+
+fn sample_ggx_vndf(wo: Vector3, alpha: f64, u: (f64, f64)) -> Vector3 {
+    // Samples a microfacet normal visible from wo, in the local frame where the surface normal
+    // is +z (Heitz 2018, "Sampling the GGX Distribution of Visible Normals").
+    let vh = unit_vector(Vector3::new(alpha * wo.x(), alpha * wo.y(), wo.z()));
+
+    let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if lensq > 0.0 {
+        Vector3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt()
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = cross(vh, t1);
+
+    let r = u.0.sqrt();
+    let phi = 2.0 * PI * u.1;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+    unit_vector(Vector3::new(alpha * nh.x(), alpha * nh.y(), nh.z().max(0.0)))
+}
+
+pub enum NormalPerturbation {
+    NormalMap(Image), // Tangent-space normals encoded as colours
+    BumpMap(Image, f64), // Heights from the image's brightness, and the height scale
+}
+
+pub struct Perturbed {
+    base: Arc<dyn Material>,
+    perturbation: NormalPerturbation,
+}
+
+impl Perturbed {
+    pub fn new(base: Arc<dyn Material>, perturbation: NormalPerturbation) -> Self {
+        Perturbed { base, perturbation }
+    }
+
+    fn shading_normal(&self, rec: &HitRecord) -> Option<Vector3> {
+        // Returns the perturbed outward normal, or None if the surface has no tangents.
+        let n = if rec.front_face { rec.normal } else { -rec.normal };
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            return None;
+        }
+
+        match &self.perturbation {
+            NormalPerturbation::NormalMap(image) => {
+                let t = unit_vector(rec.dpdu - dot(rec.dpdu, n) * n);
+                let mut b = cross(n, t);
+                if dot(b, rec.dpdv) < 0.0 {
+                    b = -b;
+                }
+
+                let c = image.sample(rec.u, rec.v);
+                let local = Vector3::new(2.0 * c.x() - 1.0, 2.0 * c.y() - 1.0, 2.0 * c.z() - 1.0);
+                Some(unit_vector(local.x() * t + local.y() * b + local.z() * n))
+            }
+            NormalPerturbation::BumpMap(image, scale) => {
+                // Displace the surface along the normal by the height, and take the normal of
+                // the displaced surface from finite differences of the height in u and v.
+                let height = |u: f64, v: f64| {
+                    let c = image.sample(u, v);
+                    scale * (c.x() + c.y() + c.z()) / 3.0
+                };
+                let du = 1.0 / (image.width() as f64);
+                let dv = 1.0 / (image.height() as f64);
+                let h = height(rec.u, rec.v);
+                let dhdu = (height(rec.u + du, rec.v) - h) / du;
+                let dhdv = (height(rec.u, rec.v + dv) - h) / dv;
+
+                let dpdu = rec.dpdu + dhdu * n;
+                let dpdv = rec.dpdv + dhdv * n;
+                let mut bumped = unit_vector(cross(dpdu, dpdv));
+                if dot(bumped, n) < 0.0 {
+                    bumped = -bumped;
+                }
+                Some(bumped)
+            }
+        }
+    }
+}
+
+impl Material for Perturbed {
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Colour {
+        self.base.albedo(r_in, rec)
+    }
+
+    fn max_walk_steps(&self) -> Option<i32> {
+        self.base.max_walk_steps()
+    }
+
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Colour,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe> {
+        let outward = match self.shading_normal(rec) {
+            Some(n) => n,
+            None => {
+                return self.base.scatter(r_in, rec, attenuation, scattered, sampler);
+            }
+        };
+        let shading_normal = if rec.front_face { outward } else { -outward };
+
+        // A shading normal facing away from the viewer can't be lit; fall back to the
+        // geometric normal rather than shading the back of the surface.
+        if dot(r_in.direction(), shading_normal) >= 0.0 {
+            return self.base.scatter(r_in, rec, attenuation, scattered, sampler);
+        }
+
+        let mut shading_rec = rec.clone();
+        shading_rec.normal = shading_normal;
+        let lobe = self.base.scatter(r_in, &shading_rec, attenuation, scattered, sampler)?;
+
+        // Only keep the scattered ray if it leaves on the same side of the geometric surface as
+        // it does of the shading surface, otherwise light would leak through the geometry.
+        let geometric_side = dot(scattered.direction(), rec.normal) > 0.0;
+        let shading_side = dot(scattered.direction(), shading_normal) > 0.0;
+        if geometric_side != shading_side {
+            return None;
+        }
+        Some(lobe)
+    }
+}
+
+pub struct Subsurface {
+    albedo: Colour, // Single-scattering albedo per channel
+    sigma_t: Colour, // Extinction coefficient per channel, the reciprocal of the mean free path
+    ior: f64,
+    g: f64,
+    max_steps: i32, // Most scattering events inside the object along a path
+}
+
+impl Subsurface {
+    pub fn new(albedo: Colour, mean_free_path: Colour, ior: f64, g: f64, max_steps: i32) -> Self {
+        let extinction = |mfp: f64| 1.0 / mfp.max(1e-6);
+        let sigma_t = Colour::new(
+            extinction(mean_free_path.x()),
+            extinction(mean_free_path.y()),
+            extinction(mean_free_path.z())
+        );
+        Subsurface { albedo, sigma_t, ior, g: g.clamp(-0.99, 0.99), max_steps }
+    }
+
+    fn transmittance(&self, distance: f64) -> Colour {
+        Colour::new(
+            (-self.sigma_t.x() * distance).exp(),
+            (-self.sigma_t.y() * distance).exp(),
+            (-self.sigma_t.z() * distance).exp()
+        )
+    }
+}
+
+impl Material for Subsurface {
+    fn albedo(&self, _: &Ray, _: &HitRecord) -> Colour {
+        self.albedo
+    }
+
+    fn max_walk_steps(&self) -> Option<i32> {
+        Some(self.max_steps)
+    }
+
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Colour,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler
+    ) -> Option<Lobe> {
+        // A random walk through a closed object. Whenever a ray that started inside the object
+        // reaches its boundary, the walk looks back along the ray: if a sampled free-flight
+        // distance is shorter than the segment, the ray actually scattered part way along it.
+        let unit_direction = unit_vector(r_in.direction());
+
+        if !rec.front_face {
+            let segment = (rec.p - r_in.origin()).length();
+
+            // Pick a channel uniformly and sample a distance with its extinction, weighting by
+            // the average pdf over all channels so that every channel stays unbiased.
+            let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+            let distance = -(1.0 - sampler.get_1d()).ln() / self.sigma_t.index(channel);
+
+            if distance < segment {
+                let tr = self.transmittance(distance);
+                let pdf = dot(self.sigma_t, tr) / 3.0;
+                *attenuation = (self.albedo * self.sigma_t * tr) / pdf;
+                *scattered = Ray::new(
+                    r_in.origin() + distance * unit_direction,
+                    sample_henyey_greenstein(unit_direction, self.g, sampler.get_2d())
+                );
+                return Some(Lobe::Diffuse);
+            }
+
+            let tr = self.transmittance(segment);
+            *attenuation = tr / ((tr.x() + tr.y() + tr.z()) / 3.0);
+        } else {
+            *attenuation = Colour::new(1.0, 1.0, 1.0);
+        }
+
+        // Cross the smooth dielectric boundary, or reflect off it, by its Fresnel reflectance.
+        let eta = if rec.front_face { 1.0 / self.ior } else { self.ior };
+        let cos_theta = dot(-unit_direction, rec.normal).min(1.0);
+        // Light that enters the object comes back out diffused, so only count the reflection
+        // off the surface as specular.
+        let (direction, lobe) = if sampler.get_1d() < fresnel_dielectric(cos_theta, eta) {
+            (reflect(unit_direction, rec.normal), Lobe::Specular)
+        } else {
+            (refract(unit_direction, rec.normal, eta), Lobe::Diffuse)
+        };
+
+        *scattered = Ray::new(rec.p, direction);
+        Some(lobe)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ rtweekend::start_sample_stream, sampler::IndependentSampler, vector3::Point3 };
+
+    fn hit_from(normal: Vector3, front_face: bool) -> HitRecord {
+        HitRecord { normal, front_face, ..HitRecord::default() }
+    }
+
+    #[test]
+    fn sheen_never_adds_energy() {
+        let material = Pbr::new(PbrParameters {
+            base_colour: Colour::new(0.9, 0.2, 0.2),
+            sheen: 1.0,
+            ..PbrParameters::default()
+        });
+        let rec = hit_from(Vector3::new(0.0, 0.0, 1.0), true);
+        // A grazing view, where the sheen is strongest.
+        let r_in = Ray::new(Point3::new(-1.0, 0.0, 0.01), Vector3::new(1.0, 0.0, -0.01));
+        let mut sampler = IndependentSampler;
+        for sample in 0..2000 {
+            start_sample_stream(3, 0, sample);
+            let mut attenuation = Colour::default();
+            let mut scattered = Ray::new(Point3::default(), Vector3::default());
+            material.scatter(&r_in, &rec, &mut attenuation, &mut scattered, &mut sampler);
+            assert!(attenuation.x() <= 1.0 && attenuation.y() <= 1.0 && attenuation.z() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn total_internal_reflection_ignores_specular() {
+        // Leaving glass at a grazing angle, every path must stay inside, even with specular 0.
+        let material = Pbr::new(PbrParameters {
+            base_colour: Colour::new(1.0, 1.0, 1.0),
+            roughness: 0.0,
+            specular: 0.0,
+            transmission: 1.0,
+            ior: 1.5,
+            ..PbrParameters::default()
+        });
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let rec = hit_from(normal, false);
+        let r_in = Ray::new(Point3::new(-1.0, 0.0, 0.1), Vector3::new(1.0, 0.0, -0.1));
+        let mut sampler = IndependentSampler;
+        for sample in 0..200 {
+            start_sample_stream(4, 0, sample);
+            let mut attenuation = Colour::default();
+            let mut scattered = Ray::new(Point3::default(), Vector3::default());
+            let lobe =
+                material.scatter(&r_in, &rec, &mut attenuation, &mut scattered, &mut sampler);
+            assert_eq!(lobe, Some(Lobe::Specular));
+            assert!(dot(scattered.direction(), normal) > 0.0);
+        }
+    }
+
+    fn shading_normal_with(perturbation: NormalPerturbation) -> Vector3 {
+        // The perturbed normal of a surface facing +z, with u along x and v along y.
+        let base = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let rec = HitRecord {
+            dpdu: Vector3::new(1.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 1.0, 0.0),
+            u: 0.375,
+            v: 0.5,
+            ..hit_from(Vector3::new(0.0, 0.0, 1.0), true)
+        };
+        Perturbed::new(base, perturbation).shading_normal(&rec).unwrap()
+    }
+
+    fn assert_near(a: Vector3, b: Vector3) {
+        assert!((a - b).length() < 1e-9, "{:?} isn't {:?}", a, b);
+    }
+
+    #[test]
+    fn flat_maps_leave_the_normal_alone() {
+        let flat = Image::new(2, 2, vec![Colour::new(0.5, 0.5, 1.0); 4]);
+        let level = Image::new(8, 8, vec![Colour::new(0.7, 0.7, 0.7); 64]);
+        let up = Vector3::new(0.0, 0.0, 1.0);
+        assert_near(shading_normal_with(NormalPerturbation::NormalMap(flat)), up);
+        assert_near(shading_normal_with(NormalPerturbation::BumpMap(level, 1.0)), up);
+    }
+
+    #[test]
+    fn tilted_maps_rotate_the_normal() {
+        // A normal map leaning 30 degrees towards +u.
+        let (sin, cos) = (30.0_f64.to_radians().sin(), 30.0_f64.to_radians().cos());
+        let encoded = Colour::new((sin + 1.0) / 2.0, 0.5, (cos + 1.0) / 2.0);
+        let leaning = Image::new(1, 1, vec![encoded]);
+        let normal = shading_normal_with(NormalPerturbation::NormalMap(leaning));
+        assert_near(normal, Vector3::new(sin, 0.0, cos));
+
+        // A bump map rising by 1 per unit of u leans the normal 45 degrees back down the slope.
+        let ramp = (0..8).map(|i| Colour::new(1.0, 1.0, 1.0) * (i as f64 / 8.0)).collect();
+        let normal = shading_normal_with(NormalPerturbation::BumpMap(Image::new(8, 1, ramp), 1.0));
+        assert_near(normal, unit_vector(Vector3::new(-1.0, 0.0, 1.0)));
+    }
+
+    fn scatter_from_above(material: &dyn Material, sample: u64) -> (Option<Lobe>, Colour) {
+        let rec = HitRecord { u: 0.5, v: 0.5, ..hit_from(Vector3::new(0.0, 0.0, 1.0), true) };
+        let r_in = Ray::new(Point3::new(0.3, 0.0, 1.0), Vector3::new(-0.3, 0.0, -1.0));
+        let mut sampler = IndependentSampler;
+        start_sample_stream(5, 0, sample);
+        let mut attenuation = Colour::default();
+        let mut scattered = Ray::new(Point3::default(), Vector3::default());
+        let lobe = material.scatter(&r_in, &rec, &mut attenuation, &mut scattered, &mut sampler);
+        (lobe, attenuation)
+    }
+
+    #[test]
+    fn mix_at_either_end_is_one_material() {
+        let red = Colour::new(0.8, 0.1, 0.1);
+        let blue = Colour::new(0.1, 0.1, 0.8);
+        let mix = |factor: MixFactor| {
+            Mix::new(Arc::new(Lambertian::new(red)), Arc::new(Metal::new(blue)), factor)
+        };
+        let flat = |brightness: f64| {
+            Image::new(1, 1, vec![Colour::new(brightness, brightness, brightness)])
+        };
+        let cases = [
+            (mix(MixFactor::Constant(0.0)), Lobe::Diffuse, red),
+            (mix(MixFactor::Constant(1.0)), Lobe::Specular, blue),
+            (mix(MixFactor::Texture(flat(0.0))), Lobe::Diffuse, red),
+            (mix(MixFactor::Texture(flat(1.0))), Lobe::Specular, blue),
+        ];
+        for (material, lobe, colour) in cases {
+            for sample in 0..100 {
+                assert_eq!(scatter_from_above(&material, sample), (Some(lobe), colour));
+            }
+        }
+    }
+
+    #[test]
+    fn mix_by_texture_follows_brightness() {
+        let image = Image::new(1, 1, vec![Colour::new(0.1, 0.2, 0.3)]);
+        let mix = Mix::new(
+            Arc::new(Lambertian::new(Colour::new(1.0, 1.0, 1.0))),
+            Arc::new(Lambertian::new(Colour::default())),
+            MixFactor::Texture(image)
+        );
+        let dark = (0..4000)
+            .filter(|sample| scatter_from_above(&mix, *sample).1 == Colour::default())
+            .count();
+        assert!((dark as f64 / 4000.0 - 0.2).abs() < 0.03);
+    }
+
+    #[test]
+    fn coated_without_a_coat_is_its_base_tinted() {
+        // A coat with the IOR of air reflects nothing, leaving the tinted base.
+        let base = Colour::new(0.5, 0.6, 0.7);
+        let tint = Colour::new(0.9, 0.5, 1.0);
+        let coated = Coated::new(Arc::new(Lambertian::new(base)), 1.0, 0.0, tint);
+        for sample in 0..100 {
+            assert_eq!(scatter_from_above(&coated, sample), (Some(Lobe::Diffuse), tint * base));
+        }
     }
 }
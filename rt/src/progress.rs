@@ -0,0 +1,210 @@
+use std::{ io::{ self, Write }, sync::Mutex, time::{ Duration, Instant } };
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::stats::RenderStats;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+pub enum ProgressStyle {
+    #[default]
+    Text, // A line on stderr that updates in place, and messages as lines of text
+    Json, // Newline-delimited JSON events on stderr, for other programs to read
+    Quiet, // Nothing but warnings and errors
+}
+
+impl ProgressStyle {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "text" => Ok(ProgressStyle::Text),
+            "json" => Ok(ProgressStyle::Json),
+            "quiet" | "none" => Ok(ProgressStyle::Quiet),
+            _ => Err(format!("unknown progress style '{}'", name)),
+        }
+    }
+}
+
+const REPORT_INTERVAL: f64 = 0.25; // Fewest seconds between progress updates within a pass
+
+// Reports how far a render has got on stderr. A render is made of passes over the image's
+// tiles, each of which takes the render from one fraction of the way through to another.
+#[derive(Debug, Default)]
+pub struct Progress {
+    pub style: ProgressStyle,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    start: Option<Instant>,
+    time_limit: Option<Duration>, // The render stops once this much time has passed
+    pass: (f64, f64), // Fractions of the render done at the start and end of the current pass
+    tiles: usize,
+    tiles_done: usize,
+    rays: u64, // Rays traced by this process so far
+    last_report: Option<Instant>,
+    line_length: usize, // Length of the progress line on the terminal, to clear it for messages
+}
+
+impl Progress {
+    pub fn new(style: ProgressStyle) -> Self {
+        Progress { style, state: Mutex::new(State::default()) }
+    }
+
+    pub fn start(&self, width: i32, height: i32, samples: i32, time_limit: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        *state = State { start: Some(Instant::now()), time_limit, ..State::default() };
+        if self.style == ProgressStyle::Json {
+            emit(json!({
+                "event": "start",
+                "width": width,
+                "height": height,
+                "samples": samples,
+            }));
+        }
+    }
+
+    pub fn start_pass(&self, from: f64, to: f64, tiles: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.pass = (from, to);
+        state.tiles = tiles;
+        state.tiles_done = 0;
+    }
+
+    pub fn tile_done(&self, rays: u64) {
+        // Called as each tile is finished, with the rays traced for it, if they're known.
+        let mut state = self.state.lock().unwrap();
+        state.tiles_done += 1;
+        state.rays += rays;
+        let due = state.last_report.is_none_or(|last| {
+            last.elapsed().as_secs_f64() >= REPORT_INTERVAL
+        });
+        if due || state.tiles_done == state.tiles {
+            state.last_report = Some(Instant::now());
+            self.report(&mut state);
+        }
+    }
+
+    pub fn message(&self, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        match self.style {
+            ProgressStyle::Text => {
+                // Write over the progress line, which the next update redraws below.
+                show(&format!("\r{:<width$}\n", text, width = state.line_length));
+                state.line_length = 0;
+            }
+            ProgressStyle::Json => emit(json!({ "event": "message", "message": text })),
+            ProgressStyle::Quiet => {}
+        }
+    }
+
+    pub fn warning(&self, text: &str) {
+        // Warnings are shown however quiet the progress is.
+        let mut state = self.state.lock().unwrap();
+        match self.style {
+            ProgressStyle::Json => emit(json!({ "event": "warning", "message": text })),
+            _ => {
+                show(&format!("\r{:<width$}\n", text, width = state.line_length));
+                state.line_length = 0;
+            }
+        }
+    }
+
+    pub fn frame(&self, frame: i32, first: i32, last: i32, time: f64) {
+        match self.style {
+            ProgressStyle::Text => {
+                self.message(&format!("Frame {} of {}..{} at {:.2}s", frame, first, last, time));
+            }
+            ProgressStyle::Json => {
+                emit(json!({
+                    "event": "frame",
+                    "frame": frame,
+                    "first": first,
+                    "last": last,
+                    "time": time,
+                }));
+            }
+            ProgressStyle::Quiet => {}
+        }
+    }
+
+    pub fn finish(&self, stats: &RenderStats) {
+        if self.style == ProgressStyle::Json {
+            let state = self.state.lock().unwrap();
+            emit(json!({
+                "event": "done",
+                "elapsed": state.start.map_or(0.0, |start| start.elapsed().as_secs_f64()),
+                "stats": stats.to_json(),
+            }));
+        }
+    }
+
+    fn report(&self, state: &mut State) {
+        let elapsed = state.start.map_or(0.0, |start| start.elapsed().as_secs_f64());
+        let (from, to) = state.pass;
+        let pass_fraction = (state.tiles_done as f64) / (state.tiles.max(1) as f64);
+        let mut fraction = from + (to - from) * pass_fraction;
+        if let Some(limit) = state.time_limit {
+            fraction = fraction.max(elapsed / limit.as_secs_f64().max(1e-9));
+        }
+        let fraction = fraction.clamp(0.0, 1.0);
+        let eta = if fraction > 0.0 { Some((elapsed * (1.0 - fraction)) / fraction) } else { None };
+        let rays_per_second = (state.rays as f64) / elapsed.max(1e-9);
+
+        match self.style {
+            ProgressStyle::Text => {
+                let line = format!(
+                    "Rendering {:5.1}%, {} elapsed, {} left, {:.0} rays/s",
+                    fraction * 100.0,
+                    format_seconds(elapsed),
+                    eta.map_or("unknown".to_string(), format_seconds),
+                    rays_per_second
+                );
+                show(&format!("\r{:<width$}", line, width = state.line_length));
+                state.line_length = line.len();
+            }
+            ProgressStyle::Json => {
+                emit(json!({
+                    "event": "progress",
+                    "percent": fraction * 100.0,
+                    "elapsed": elapsed,
+                    "eta": eta,
+                    "rays_per_second": rays_per_second,
+                    "tiles_done": state.tiles_done,
+                    "tiles": state.tiles,
+                }));
+            }
+            ProgressStyle::Quiet => {}
+        }
+    }
+}
+
+impl Clone for Progress {
+    fn clone(&self) -> Self {
+        // A copy reports in the same style, but for a render of its own.
+        Progress::new(self.style)
+    }
+}
+
+fn emit(event: serde_json::Value) {
+    // One event per line, written whole so events from different threads don't interleave.
+    show(&format!("{}\n", event));
+}
+
+fn show(text: &str) {
+    // A program that stops reading stderr part way through shouldn't stop the render.
+    let mut stderr = io::stderr().lock();
+    let _ = stderr.write_all(text.as_bytes()).and_then(|_| stderr.flush());
+}
+
+fn format_seconds(seconds: f64) -> String {
+    // Durations such as 42s, 3m07s or 1h02m30s.
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        return format!("{}h{:02}m{:02}s", seconds / 3600, (seconds / 60) % 60, seconds % 60);
+    }
+    if seconds >= 60 {
+        return format!("{}m{:02}s", seconds / 60, seconds % 60);
+    }
+    format!("{}s", seconds)
+}
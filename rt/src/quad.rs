@@ -1,4 +1,4 @@
-use std::{ rc::Rc };
+use std::{ sync::Arc };
 
 use crate::{
     vector3::{ Point3, Vector3, cross, unit_vector, dot },
@@ -6,13 +6,14 @@ use crate::{
     aabb::Aabb,
     hittable::{ Hittable, HitRecord },
     hittable_list::HittableList,
+    stats,
 };
 
 pub struct Plane {
     q: Point3,
     u: Vector3,
     v: Vector3,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
     bbox: Aabb,
     normal: Vector3,
     d: f64,
@@ -20,7 +21,7 @@ pub struct Plane {
 }
 
 impl Plane {
-    pub fn new(q: Point3, u: Vector3, v: Vector3, m: Rc<dyn Material>) -> Self {
+    pub fn new(q: Point3, u: Vector3, v: Vector3, m: Arc<dyn Material>) -> Self {
         let n = cross(u, v);
         let normal = unit_vector(n);
         let d = dot(normal, q);
@@ -33,13 +34,13 @@ impl Plane {
         // Given the hit point in plane coordinates, return false if it is outside the
         // primitive, otherwise set the hit record UV coordinates and return true.
 
-        if a < 0.0 || 1.0 < a || b < 0.0 || 1.0 < b {
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
             return false;
         }
 
         rec.u = a;
         rec.v = b;
-        return true;
+        true
     }
 }
 
@@ -50,6 +51,8 @@ impl Hittable for Plane {
         ray_t: crate::interval::Interval,
         rec: &mut crate::hittable::HitRecord
     ) -> bool {
+        stats::count_primitive_test();
+
         let denom = dot(self.normal, r.direction());
 
         // No hit if the ray is parallel to the plane.
@@ -78,16 +81,18 @@ impl Hittable for Plane {
         rec.p = intersection;
         rec.mat = self.mat.clone();
         rec.set_face_normal(r, &self.normal);
+        rec.dpdu = self.u;
+        rec.dpdv = self.v;
 
-        return true;
+        true
     }
 
     fn bounding_box(&self) -> Aabb {
-        return self.bbox;
+        self.bbox
     }
 }
 
-pub fn cuboid(a: Point3, b: Point3, mat: Rc<dyn Material>) -> Rc<HittableList> {
+pub fn cuboid(a: Point3, b: Point3, mat: Arc<dyn Material>) -> Arc<HittableList> {
     // Returns the 3D box (six sides) that contains the two opposite vertices a & b.
 
     let mut sides = HittableList::new();
@@ -100,12 +105,12 @@ pub fn cuboid(a: Point3, b: Point3, mat: Rc<dyn Material>) -> Rc<HittableList> {
     let dy = Vector3::new(0.0, max.y() - min.y(), 0.0);
     let dz = Vector3::new(0.0, 0.0, max.z() - min.z());
 
-    sides.add(Rc::new(Plane::new(Point3::new(min.x(), min.y(), max.z()), dx, dy, mat.clone()))); // front
-    sides.add(Rc::new(Plane::new(Point3::new(max.x(), min.y(), max.z()), -dz, dy, mat.clone()))); // right
-    sides.add(Rc::new(Plane::new(Point3::new(max.x(), min.y(), min.z()), -dx, dy, mat.clone()))); // back
-    sides.add(Rc::new(Plane::new(Point3::new(min.x(), min.y(), min.z()), dz, dy, mat.clone()))); // left
-    sides.add(Rc::new(Plane::new(Point3::new(min.x(), max.y(), max.z()), dx, -dz, mat.clone()))); // top
-    sides.add(Rc::new(Plane::new(Point3::new(min.x(), min.y(), min.z()), dx, dz, mat))); // bottom
+    sides.add(Arc::new(Plane::new(Point3::new(min.x(), min.y(), max.z()), dx, dy, mat.clone()))); // front
+    sides.add(Arc::new(Plane::new(Point3::new(max.x(), min.y(), max.z()), -dz, dy, mat.clone()))); // right
+    sides.add(Arc::new(Plane::new(Point3::new(max.x(), min.y(), min.z()), -dx, dy, mat.clone()))); // back
+    sides.add(Arc::new(Plane::new(Point3::new(min.x(), min.y(), min.z()), dz, dy, mat.clone()))); // left
+    sides.add(Arc::new(Plane::new(Point3::new(min.x(), max.y(), max.z()), dx, -dz, mat.clone()))); // top
+    sides.add(Arc::new(Plane::new(Point3::new(min.x(), min.y(), min.z()), dx, dz, mat))); // bottom
 
-    return Rc::new(sides);
+    Arc::new(sides)
 }
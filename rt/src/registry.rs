@@ -0,0 +1,156 @@
+use std::{ collections::HashMap, sync::{ Arc, LazyLock, RwLock } };
+
+use serde_yaml::Value;
+
+use crate::{ hittable::Hittable, material::Material };
+
+// Constructors for material and object types beyond the built-in ones, so programs using the
+// renderer can add their own 'type:' names to scene files. A constructor is given the material's
+// or object's settings from the scene, and the materials defined before it by name.
+//
+// Registered types are looked up before the built-in ones, so registering a built-in name
+// replaces it. The usual settings still apply on top: normal_map and bump_map for materials,
+// and rotate_y and translate for objects.
+//
+// Worker processes build the scene themselves, so the stock `rt worker` only knows the built-in
+// types. Workers for scenes using registered types must register them too and call
+// distributed::serve; the coordinator lists the registered types a scene uses, and workers
+// missing any of them refuse it.
+
+pub type Materials = HashMap<String, Arc<dyn Material>>;
+
+pub type MaterialConstructor = Arc<
+    dyn (Fn(&Value, &Materials) -> Result<Arc<dyn Material>, Box<dyn std::error::Error>>) +
+        Send +
+        Sync
+>;
+
+pub type ObjectConstructor = Arc<
+    dyn (Fn(&Value, &Materials) -> Result<Arc<dyn Hittable>, Box<dyn std::error::Error>>) +
+        Send +
+        Sync
+>;
+
+#[derive(Default)]
+struct Registry {
+    materials: HashMap<String, MaterialConstructor>,
+    objects: HashMap<String, ObjectConstructor>,
+}
+
+static REGISTRY: LazyLock<RwLock<Registry>> = LazyLock::new(|| RwLock::new(Registry::default()));
+
+pub fn register_material<F>(type_name: &str, constructor: F)
+    where
+        F: (Fn(&Value, &Materials) -> Result<Arc<dyn Material>, Box<dyn std::error::Error>>) +
+            Send +
+            Sync +
+            'static
+{
+    let mut registry = REGISTRY.write().unwrap();
+    registry.materials.insert(type_name.to_string(), Arc::new(constructor));
+}
+
+pub fn register_object<F>(type_name: &str, constructor: F)
+    where
+        F: (Fn(&Value, &Materials) -> Result<Arc<dyn Hittable>, Box<dyn std::error::Error>>) +
+            Send +
+            Sync +
+            'static
+{
+    let mut registry = REGISTRY.write().unwrap();
+    registry.objects.insert(type_name.to_string(), Arc::new(constructor));
+}
+
+pub fn material_constructor(type_name: &str) -> Option<MaterialConstructor> {
+    REGISTRY.read().unwrap().materials.get(type_name).cloned()
+}
+
+pub fn object_constructor(type_name: &str) -> Option<ObjectConstructor> {
+    REGISTRY.read().unwrap().objects.get(type_name).cloned()
+}
+
+pub fn registered_types(scene: &Value) -> (Vec<String>, Vec<String>) {
+    // The registered material and object types a scene uses, which a worker rendering it must
+    // have registered too.
+    let types = |section: &str| -> Vec<String> {
+        let entries = scene.get(section).and_then(Value::as_sequence).cloned().unwrap_or_default();
+        let mut names: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| {
+                // Materials are single-key maps of a name to the settings.
+                let settings = match section {
+                    "materials" => entry.as_mapping()?.values().next()?,
+                    _ => entry,
+                };
+                settings.get("type")?.as_str().map(str::to_string)
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    };
+    let materials = types("materials")
+        .into_iter()
+        .filter(|name| material_constructor(name).is_some())
+        .collect();
+    let objects = types("objects")
+        .into_iter()
+        .filter(|name| object_constructor(name).is_some())
+        .collect();
+    (materials, objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        colour::Colour,
+        hittable::HitRecord,
+        interval::Interval,
+        material::Lambertian,
+        ray::Ray,
+        scene::Scene,
+        sphere::Sphere,
+        vector3::{ Point3, Vector3 },
+    };
+
+    // The names are only used here, as the registry is shared by every test in the process.
+    const SCENE: &str = "
+camera:
+  image_width: 8
+materials:
+  - chalk: { type: TestChalk, shade: 0.25 }
+objects:
+  - { type: TestBall, size: 2.0, mat: chalk }
+";
+
+    #[test]
+    fn registered_types_can_be_used_in_scenes() {
+        register_material("TestChalk", |settings, _| {
+            let shade = settings.get("shade").and_then(Value::as_f64).ok_or("no shade")?;
+            Ok(Arc::new(Lambertian::new(Colour::new(shade, shade, shade))))
+        });
+        register_object("TestBall", |settings, materials| {
+            let size = settings.get("size").and_then(Value::as_f64).ok_or("no size")?;
+            let name = settings.get("mat").and_then(Value::as_str).ok_or("no mat")?;
+            let mat = materials.get(name).ok_or("unknown material")?.clone();
+            Ok(Arc::new(Sphere::new(Point3::default(), size, mat)))
+        });
+
+        let scene = Scene::from_yaml_str("registry.yaml", SCENE).unwrap();
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert_eq!(rec.mat.albedo(&r, &rec), Colour::new(0.25, 0.25, 0.25));
+
+        // Workers must be told about both, but not about built-in types.
+        let mut yaml: Value = serde_yaml::from_str(SCENE).unwrap();
+        let sphere: Value = serde_yaml::from_str("{ type: Sphere, radius: 1.0 }").unwrap();
+        yaml["objects"].as_sequence_mut().unwrap().push(sphere);
+        assert_eq!(
+            registered_types(&yaml),
+            (vec!["TestChalk".to_string()], vec!["TestBall".to_string()])
+        );
+    }
+}
@@ -1,27 +1,50 @@
-use rand;
-
 // Constants
 
 //const INFINITY: f64 = std::f64::INFINITY;
-const PI: f64 = 3.1415926535897932385;
+use std::{ cell::Cell, f64::consts::PI };
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+const PCG_INCREMENT: u64 = 1442695040888963407;
+
+thread_local! {
+    // State of this thread's PCG32 generator. Renders restart it for every sample with
+    // start_sample_stream, so results don't depend on the order samples are taken in.
+    static RNG_STATE: Cell<u64> = Cell::new(rand::random::<u64>());
+}
 
 // Utility Functions
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
-    return (degrees * PI) / 180.0;
+    (degrees * PI) / 180.0
 }
 
-pub fn random_f64() -> f64 {
-    // Returns a random real in [0,1).
-    return rand::random::<f64>();
+fn random_u32() -> u32 {
+    // PCG-XSH-RR: advance the LCG state and permute the old state into the output.
+    RNG_STATE.with(|state| {
+        let old = state.get();
+        state.set(old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(PCG_INCREMENT));
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    })
 }
 
-pub fn random_f64_range(min: f64, max: f64) -> f64 {
-    // Returns a random real in [min,max).
-    return min + (max - min) * random_f64();
+pub fn splitmix64(mut x: u64) -> u64 {
+    // A strong 64-bit mixing function, so neighbouring pixels and samples get unrelated streams.
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
 }
 
-// pub fn random_int(min: i32, max: i32) -> i32 {
-//     // Returns a random integer in [min,max].
-//     return random_f64_range(min as f64, (max as f64) + 1.0) as i32;
-// }
+pub fn start_sample_stream(seed: u64, pixel: u64, sample: u64) {
+    // Restart this thread's generator on the stream belonging to one sample of one pixel.
+    let state = splitmix64(seed ^ splitmix64(pixel ^ splitmix64(sample)));
+    RNG_STATE.with(|s| s.set(state));
+}
+
+pub fn random_f64() -> f64 {
+    // Returns a random real in [0,1), using 53 random bits.
+    let bits = ((random_u32() as u64) << 32) | (random_u32() as u64);
+    ((bits >> 11) as f64) * (1.0 / ((1u64 << 53) as f64))
+}
@@ -0,0 +1,383 @@
+use serde::Deserialize;
+
+use crate::rtweekend::{ random_f64, splitmix64 };
+
+// Source of the sample values used to build a path. Every dimension of the path (pixel
+// position, each bounce's direction, ...) asks for its values in the same order on every sample,
+// so samplers can spread the values of each dimension evenly across a pixel's samples.
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "independent" | "random" => Ok(SamplerKind::Independent),
+            "stratified" | "jittered" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler '{}'", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        }
+    }
+
+    pub fn create(&self, seed: u64, samples_per_pixel: i32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+fn dimension_hash(seed: u64, pixel: u64, dimension: u32) -> u64 {
+    // A value that is unrelated between pixels and dimensions, for decorrelating them.
+    splitmix64(seed ^ splitmix64(pixel ^ splitmix64(dimension as u64)))
+}
+
+// Uniform random values; the sample's random number stream is already set up by the renderer.
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, _: u64, _: u64) {}
+
+    fn get_1d(&mut self) -> f64 {
+        random_f64()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (random_f64(), random_f64())
+    }
+}
+
+// Jittered stratification: each dimension is split into samples_per_pixel strata, and every
+// sample of a pixel falls in a different stratum, in an order shuffled per pixel and dimension.
+pub struct StratifiedSampler {
+    seed: u64,
+    strata: u32,
+    x_strata: u32, // Columns of the 2D strata grid
+    y_strata: u32, // Rows of the 2D strata grid
+    pixel: u64,
+    sample: u64,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: i32) -> Self {
+        let strata = samples_per_pixel.max(1) as u32;
+        let x_strata = ((strata as f64).sqrt().floor() as u32).max(1);
+        let y_strata = strata.div_ceil(x_strata);
+        StratifiedSampler { seed, strata, x_strata, y_strata, pixel: 0, sample: 0, dimension: 0 }
+    }
+
+    fn stratum(&mut self, count: u32) -> Option<u32> {
+        // Returns the sample's stratum out of count in the next dimension, or None once the
+        // pixel has more samples than strata.
+        let hash = dimension_hash(self.seed, self.pixel, self.dimension) as u32;
+        self.dimension += 1;
+        if self.sample >= (count as u64) {
+            return None;
+        }
+        Some(permutation_element(self.sample as u32, count, hash))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        match self.stratum(self.strata) {
+            Some(s) => ((s as f64) + random_f64()) / (self.strata as f64),
+            None => random_f64(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        match self.stratum(self.x_strata * self.y_strata) {
+            Some(s) => {
+                let x = s % self.x_strata;
+                let y = s / self.x_strata;
+                (
+                    ((x as f64) + random_f64()) / (self.x_strata as f64),
+                    ((y as f64) + random_f64()) / (self.y_strata as f64),
+                )
+            }
+            None => (random_f64(), random_f64()),
+        }
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// The Halton sequence, using the radical inverse in the n-th prime base for dimension n.
+// Each pixel's points are shifted by a random toroidal offset per dimension (a Cranley-Patterson
+// rotation) so neighbouring pixels don't share the same pattern. Dimensions beyond the prime
+// table fall back to independent random values.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    sample: u64,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler { seed, pixel: 0, sample: 0, dimension: 0 }
+    }
+}
+
+fn radical_inverse(base: u64, mut a: u64) -> f64 {
+    let inv_base = 1.0 / (base as f64);
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    while a > 0 {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    ((reversed_digits as f64) * inv_base_m).min(1.0 - f64::EPSILON)
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        if (dimension as usize) >= PRIMES.len() {
+            return random_f64();
+        }
+
+        let offset = ((dimension_hash(self.seed, self.pixel, dimension) >> 11) as f64) /
+            ((1u64 << 53) as f64);
+        let value = radical_inverse(PRIMES[dimension as usize], self.sample) + offset;
+        value - value.floor()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Owen-scrambled Sobol points. Each pair of dimensions uses the first two Sobol dimensions,
+// whose 2D projections are well stratified, with the sample index shuffled independently per
+// pair so the pairs don't correlate with each other (Burley 2020, "Practical Hash-based Owen
+// Scrambling").
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    sample: u64,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        SobolSampler { seed, pixel: 0, sample: 0, dimension: 0 }
+    }
+
+    fn next_hash(&mut self) -> u64 {
+        let hash = dimension_hash(self.seed, self.pixel, self.dimension);
+        self.dimension += 1;
+        hash
+    }
+}
+
+fn sobol_dimension_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_dimension_1(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut v: u32 = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn to_unit_float(x: u32) -> f64 {
+    (x as f64) / 4294967296.0
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let hash = self.next_hash();
+        let index = nested_uniform_scramble(self.sample as u32, hash as u32);
+        let x = nested_uniform_scramble(sobol_dimension_0(index), (hash >> 32) as u32);
+        to_unit_float(x)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let hash = self.next_hash();
+        let index = nested_uniform_scramble(self.sample as u32, hash as u32);
+        let x = nested_uniform_scramble(sobol_dimension_0(index), (hash >> 32) as u32);
+        let y = nested_uniform_scramble(
+            sobol_dimension_1(index),
+            splitmix64(hash) as u32
+        );
+        (to_unit_float(x), to_unit_float(y))
+    }
+}
+
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    // Element i of a pseudo-random permutation of 0..l chosen by p (Kensler 2013,
+    // "Correlated Multi-Jittered Sampling").
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | (p >> 27));
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::start_sample_stream;
+
+    fn values(kind: SamplerKind, seed: u64, pixel: u64, samples: u64) -> Vec<Vec<f64>> {
+        // Each sample's first few dimensions, asked for as the renderer does: a 2D pixel
+        // position, then a mix of 1D and 2D values.
+        let mut sampler = kind.create(seed, samples as i32);
+        (0..samples)
+            .map(|sample| {
+                start_sample_stream(seed, pixel, sample);
+                sampler.start_pixel_sample(pixel, sample);
+                let (x, y) = sampler.get_2d();
+                let z = sampler.get_1d();
+                let (u, v) = sampler.get_2d();
+                vec![x, y, z, u, v, sampler.get_1d()]
+            })
+            .collect()
+    }
+
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() as f64;
+        let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+        let covariance = a.iter().zip(b).map(|(a, b)| (a - mean_a) * (b - mean_b)).sum::<f64>();
+        let spread = |x: &[f64], mean: f64| x.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+        covariance / (spread(a, mean_a) * spread(b, mean_b)).sqrt()
+    }
+
+    #[test]
+    fn stratified_samples_fall_one_per_stratum() {
+        // 16 samples make 16 strata in 1D and a 4 by 4 grid in 2D.
+        let values = values(SamplerKind::Stratified, 3, 17, 16);
+        let mut line = vec![0; 16];
+        let mut grid = vec![0; 16];
+        for sample in &values {
+            line[(sample[2] * 16.0) as usize] += 1;
+            grid[((sample[1] * 4.0) as usize) * 4 + ((sample[0] * 4.0) as usize)] += 1;
+        }
+        assert!(line.iter().all(|count| *count == 1), "{:?}", line);
+        assert!(grid.iter().all(|count| *count == 1), "{:?}", grid);
+    }
+
+    #[test]
+    fn halton_and_sobol_are_deterministic_and_in_range() {
+        for kind in [SamplerKind::Halton, SamplerKind::Sobol] {
+            let first = values(kind, 5, 42, 64);
+            assert!(first.iter().flatten().all(|value| (0.0..1.0).contains(value)), "{:?}", kind);
+            assert_eq!(first, values(kind, 5, 42, 64), "{:?}", kind);
+            assert_ne!(first, values(kind, 6, 42, 64), "{:?}", kind);
+            assert_ne!(first, values(kind, 5, 43, 64), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn dimensions_are_not_correlated() {
+        // Over 1024 samples, unrelated dimensions give correlations of around 0.03, while
+        // dimensions sharing a pattern give ones near 1.
+        for kind in [SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+            let values = values(kind, 9, 3, 1024);
+            let dimension = |d: usize| values.iter().map(|sample| sample[d]).collect::<Vec<_>>();
+            for a in 0..6 {
+                for b in a + 1..6 {
+                    let r = correlation(&dimension(a), &dimension(b));
+                    assert!(r.abs() < 0.15, "{:?} dimensions {} and {}: {}", kind, a, b, r);
+                }
+            }
+        }
+    }
+}
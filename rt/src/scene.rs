@@ -0,0 +1,231 @@
+use std::{ collections::HashMap, sync::Arc, time::{ Duration, Instant } };
+
+use serde_yaml::Value;
+
+use crate::{
+    camera::Camera,
+    film::{ Framebuffer, Region },
+    helper::{ extract_materials, extract_material_ids, extract_objects, get_nested_yaml_value },
+    hittable::Hittable,
+    hittable_list::HittableList,
+    progress::ProgressStyle,
+    scene_file,
+};
+
+// A world to render and the camera to render it with, read from a scene file or built in code.
+pub struct Scene {
+    pub camera: Camera,
+    pub world: HittableList,
+    pub filename: String, // Name the images are written under, without the extension
+    file_objects: Option<Vec<Arc<dyn Hittable>>>, // The world as the scene file built it
+}
+
+impl Scene {
+    pub fn new(camera: Camera) -> Self {
+        Scene {
+            camera,
+            world: HittableList::new(),
+            filename: "output_image".to_string(),
+            file_objects: None,
+        }
+    }
+
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
+        self.world.add(object);
+    }
+
+    pub fn load(scene_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        // Resolve the scene's includes and variables before building it.
+        let parse_start = Instant::now();
+        let yaml_content = serde_yaml::to_string(&scene_file::load(scene_path)?)?;
+        let parse_time = parse_start.elapsed();
+        let build_start = Instant::now();
+        let mut scene = Scene::from_yaml_str(scene_path, &yaml_content)?;
+        scene.camera.set_load_times(parse_time, build_start.elapsed());
+        Ok(scene)
+    }
+
+    pub fn from_yaml_str(
+        scene_path: &str,
+        yaml_content: &str
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (world, camera, filename) = parse_scene(scene_path, yaml_content)?;
+        let file_objects = Some(world.objects.clone());
+        Ok(Scene { camera, world, filename, file_objects })
+    }
+
+    fn is_from_file(&self) -> bool {
+        // Workers build the world from the scene file, so they can only help render it while
+        // it's unchanged.
+        self.file_objects.as_ref().is_some_and(|objects| {
+            objects.len() == self.world.objects.len() &&
+                objects
+                    .iter()
+                    .zip(&self.world.objects)
+                    .all(|(file_object, object)| Arc::ptr_eq(file_object, object))
+        })
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new(Camera::default())
+    }
+}
+
+// Settings for a render that take precedence over the scene's camera, such as the options given
+// on the command line.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub samples: Option<i32>,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub time_limit: Option<Duration>, // Render progressively until this much time has passed
+    pub workers: Vec<String>,
+    pub region: Option<Region>,
+    pub crop: bool,
+    pub stats_json: bool,
+    pub progress: ProgressStyle,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        // Programs using the renderer report progress themselves, if at all.
+        RenderSettings {
+            samples: None,
+            seed: None,
+            threads: None,
+            time_limit: None,
+            workers: Vec::new(),
+            region: None,
+            crop: false,
+            stats_json: false,
+            progress: ProgressStyle::Quiet,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn apply(&self, cam: &mut Camera) {
+        if self.time_limit.is_some() {
+            cam.progressive = true;
+            cam.time_limit = self.time_limit;
+        }
+        if let Some(samples) = self.samples {
+            cam.samples_per_pixel = samples;
+            cam.max_samples = samples;
+        }
+        if self.seed.is_some() {
+            cam.seed = self.seed;
+        }
+        if let Some(threads) = self.threads {
+            cam.threads = threads;
+        }
+        if !self.workers.is_empty() {
+            cam.workers = self.workers.clone();
+        }
+        if self.region.is_some() {
+            cam.region = self.region;
+            cam.crop = self.crop;
+        }
+        if self.stats_json {
+            cam.stats_json = true;
+        }
+        cam.set_progress_style(self.progress);
+    }
+}
+
+pub fn render(
+    scene: &Scene,
+    settings: &RenderSettings
+) -> Result<Framebuffer, Box<dyn std::error::Error>> {
+    // Render the scene into memory. The scene is left as it was, so it can be rendered again.
+    let mut cam = scene.camera.clone();
+    settings.apply(&mut cam);
+    if !cam.workers.is_empty() && !scene.is_from_file() {
+        return Err("workers can only render a world as it was read from the scene file".into());
+    }
+    cam.render_framebuffer(&scene.world, &scene.filename)
+}
+
+pub fn parse_scene(
+    scene_path: &str,
+    yaml_content: &str
+) -> Result<(HittableList, Camera, String), Box<dyn std::error::Error>> {
+    // World
+    let mut world = HittableList::new();
+
+    let yaml_value: Value = serde_yaml::from_str(yaml_content)?;
+    let mut materials = HashMap::new();
+    let mut material_ids = HashMap::new();
+
+    // Extract materials configuration
+    if let Some(materials_value) = yaml_value.get("materials") {
+        materials = extract_materials(materials_value)?;
+        material_ids = extract_material_ids(materials_value);
+    } else {
+        eprintln!("'materials' key not found in the YAML file");
+    }
+
+    if let Some(object_values) = yaml_value.get("objects") {
+        let objects = extract_objects(object_values, &materials, &material_ids)?;
+
+        for obj in objects {
+            world.add(obj);
+        }
+    }
+
+    // Camera
+    if yaml_value.get("camera").is_none() {
+        return Err("the scene has no 'camera' section".into());
+    }
+    let cam = Camera::from_yaml_str(scene_path, yaml_content).map_err(
+        |err| format!("camera: {}", err)
+    )?;
+
+    let filename_value: Option<String> = get_nested_yaml_value(&yaml_value, "filename");
+
+    let filename = match filename_value {
+        Some(name) => name,
+        None => "output_image".to_string(), // Provide a default filename if it's not present
+    };
+
+    Ok((world, cam, filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(yaml: &str) -> String {
+        match Scene::from_yaml_str("test.yaml", yaml) {
+            Ok(_) => panic!("scene should not load: {}", yaml),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn reports_bad_scenes() {
+        let materials = "camera: {}\nmaterials: [{ red: { colour: [1.0, 0.0, 0.0] } }]\n";
+        assert!(Scene::from_yaml_str("test.yaml", materials).is_ok());
+        assert!(Scene::from_yaml_str("test.yaml", "objects: [").is_err());
+        assert_eq!(
+            error(&format!("{}objects: [{{ type: Sphere, mat: nope }}]", materials)),
+            "object 1 (Sphere): unknown material 'nope'"
+        );
+        assert_eq!(
+            error(&format!("{}objects: [{{ type: Sphere, mat: red }}, {{ radius: 2 }}]", materials)),
+            "object 2 has no type"
+        );
+        assert_eq!(
+            error(&format!("{}objects: [{{ type: Volume, name: smoke }}]", materials)),
+            "object 'smoke': volume is missing the 'grid' key"
+        );
+        assert_eq!(
+            error("camera: {}\nmaterials: [{ glaze: { type: Coated, base: clay } }]"),
+            "material 'glaze': material 'clay' must be defined before it is referenced"
+        );
+        assert_eq!(error("objects: []"), "the scene has no 'camera' section");
+        assert_eq!(error("camera: { sampler: dice }"), "camera: unknown sampler 'dice'");
+    }
+}
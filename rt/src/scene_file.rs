@@ -0,0 +1,475 @@
+use std::{ collections::HashSet, path::{ Path, PathBuf } };
+
+use serde_yaml::{ Mapping, Value };
+
+use crate::{
+    camera::Camera,
+    helper::{ canonical_materials, canonical_objects, get_nested_yaml_value },
+};
+
+// Scene files can include other scene files, such as shared material libraries, and define
+// variables. Loading a scene resolves both into one document for the rest of the renderer.
+//
+// Included files are merged in the order listed and the including file is merged last, so it
+// overrides them: materials with the same name replace earlier ones, objects are added after
+// earlier ones, and every other setting replaces the earlier one, key by key inside sections such
+// as camera.
+//
+// Paths to assets such as normal maps and volume grids in an included file are relative to that
+// file, and are rewritten to be relative to the including file as it is merged. Paths starting
+// with a variable are left as they are.
+//
+// Strings may contain ${name}, which is replaced by the variable of that name from the vars
+// sections, or otherwise the environment variable. A string that is only ${name} takes the
+// variable's value as it is, so numbers and lists can be variables too.
+
+const MAX_DEPTH: usize = 16; // Deepest nesting of includes, or of variables naming variables
+
+pub fn load(path: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut scene = load_with_includes(Path::new(path), &mut HashSet::new())?;
+
+    let vars = match remove_key(&mut scene, "vars") {
+        Some(Value::Mapping(vars)) => vars,
+        Some(_) => {
+            return Err("'vars' must be a map of names to values".into());
+        }
+        None => Mapping::new(),
+    };
+    substitute(&mut scene, &vars, 0)?;
+    Ok(scene)
+}
+
+fn load_with_includes(
+    path: &Path,
+    loading: &mut HashSet<PathBuf>
+) -> Result<Value, Box<dyn std::error::Error>> {
+    // Loading holds the files being loaded further up, to catch files that include themselves.
+    let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if loading.contains(&key) {
+        return Err(format!("'{}' includes itself", path.display()).into());
+    }
+    if loading.len() >= MAX_DEPTH {
+        return Err(format!("includes nested too deeply at '{}'", path.display()).into());
+    }
+
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        format!("could not read '{}': {}", path.display(), err)
+    })?;
+    let mut scene = parse(path, &content).map_err(|err| {
+        format!("could not parse '{}': {}", path.display(), err)
+    })?;
+    if scene.is_null() {
+        scene = Value::Mapping(Mapping::new());
+    }
+    if !scene.is_mapping() {
+        return Err(format!("'{}' is not a scene", path.display()).into());
+    }
+
+    let includes = match remove_key(&mut scene, "include") {
+        Some(Value::String(include)) => vec![include],
+        Some(Value::Sequence(includes)) => {
+            includes
+                .into_iter()
+                .map(|include| include.as_str().map(|include| include.to_string()))
+                .collect::<Option<Vec<String>>>()
+                .ok_or("'include' must be a file or list of files")?
+        }
+        Some(_) => {
+            return Err("'include' must be a file or list of files".into());
+        }
+        None => Vec::new(),
+    };
+
+    if !loading.is_empty() {
+        rebase_asset_paths(&mut scene, path.parent().unwrap_or(Path::new("")));
+    }
+
+    loading.insert(key.clone());
+    let mut merged = Value::Mapping(Mapping::new());
+    for include in includes {
+        // Include paths may use environment variables and the file's own variables, and are
+        // relative to the including file.
+        let mut include = Value::String(include);
+        let vars = match scene.get("vars") {
+            Some(Value::Mapping(vars)) => vars.clone(),
+            _ => Mapping::new(),
+        };
+        substitute(&mut include, &vars, 0)?;
+        let include = include.as_str().ok_or("include paths must be text")?.to_string();
+        let include_path = path.parent().unwrap_or(Path::new("")).join(include);
+
+        let included = load_with_includes(&include_path, loading)?;
+        merged = merge(merged, included);
+    }
+    loading.remove(&key);
+
+    Ok(merge(merged, scene))
+}
+
+fn rebase_asset_paths(scene: &mut Value, directory: &Path) {
+    // Makes the relative asset paths of an included file relative to the directory it's in, as
+    // seen from the including file.
+    let rebase = |value: &mut Value| {
+        if let Value::String(text) = value {
+            if Path::new(text.as_str()).is_relative() && !text.starts_with("${") {
+                *text = directory.join(text.as_str()).to_string_lossy().into_owned();
+            }
+        }
+    };
+    if let Some(Value::Sequence(materials)) = scene.get_mut("materials") {
+        for material in materials.iter_mut().filter_map(Value::as_mapping_mut) {
+            for (_, settings) in material.iter_mut() {
+                for key in ["normal_map", "bump_map", "factor_map"] {
+                    if let Some(value) = settings.get_mut(key) {
+                        rebase(value);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(Value::Sequence(objects)) = scene.get_mut("objects") {
+        for object in objects {
+            if object.get("type").and_then(Value::as_str) == Some("Volume") {
+                if let Some(value) = object.get_mut("grid") {
+                    rebase(value);
+                }
+            }
+        }
+    }
+}
+
+fn parse(path: &Path, content: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    // Scenes may be written in JSON or TOML as well as YAML, with the same layout.
+    match format(path) {
+        "json" => Ok(serde_json::from_str(content)?),
+        "toml" => Ok(toml::from_str(content)?),
+        _ => Ok(serde_yaml::from_str(content)?),
+    }
+}
+
+fn format(path: &Path) -> &'static str {
+    // The scene format given by the file's extension, YAML unless it says otherwise.
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("json") => "json",
+        Some(extension) if extension.eq_ignore_ascii_case("toml") => "toml",
+        _ => "yaml",
+    }
+}
+
+pub fn save(path: &str, scene: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    // Writes the scene in the format given by the file's extension.
+    let content = match format(Path::new(path)) {
+        "json" => serde_json::to_string_pretty(scene)? + "\n",
+        "toml" => toml::to_string(scene)?,
+        _ => serde_yaml::to_string(scene)?,
+    };
+    std::fs::write(path, content).map_err(|err| format!("could not write '{}': {}", path, err))?;
+    Ok(())
+}
+
+pub fn canonical(scene_path: &str, scene: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    // The scene as the renderer reads it, with every default written out.
+    let yaml_content = serde_yaml::to_string(scene)?;
+    let camera = Camera::from_yaml_str(scene_path, &yaml_content)?;
+    let mut camera_value = camera.to_yaml();
+    if let Some(turntable) = scene.get("camera").and_then(|camera| camera.get("turntable")) {
+        camera_value.as_mapping_mut().unwrap().insert(Value::from("turntable"), turntable.clone());
+    }
+
+    let mut canonical = Mapping::new();
+    canonical.insert(Value::from("camera"), camera_value);
+    canonical.insert(
+        Value::from("materials"),
+        canonical_materials(scene.get("materials").unwrap_or(&Value::Null))
+    );
+    canonical.insert(
+        Value::from("objects"),
+        canonical_objects(scene.get("objects").unwrap_or(&Value::Null))
+    );
+    if let Some(animation) = scene.get("animation") {
+        canonical.insert(Value::from("animation"), animation.clone());
+    }
+    let filename: String = get_nested_yaml_value(scene, "filename").unwrap_or(
+        "output_image".to_string()
+    );
+    canonical.insert(Value::from("filename"), Value::from(filename));
+    Ok(Value::Mapping(canonical))
+}
+
+fn merge(base: Value, overlay: Value) -> Value {
+    // The two scenes combined, with overlay taking precedence.
+    let (Value::Mapping(mut base), Value::Mapping(overlay)) = (base, overlay) else {
+        return Value::Null;
+    };
+    for (key, value) in overlay {
+        let merged = match (key.as_str(), base.remove(&key)) {
+            (Some("materials"), Some(existing)) => merge_materials(existing, value),
+            (Some("objects"), Some(Value::Sequence(mut existing))) => {
+                if let Value::Sequence(objects) = value {
+                    existing.extend(objects);
+                    Value::Sequence(existing)
+                } else {
+                    value
+                }
+            }
+            (_, Some(existing)) => merge_settings(existing, value),
+            (_, None) => value,
+        };
+        base.insert(key, merged);
+    }
+    Value::Mapping(base)
+}
+
+fn merge_settings(base: Value, overlay: Value) -> Value {
+    // Maps are merged key by key, and anything else is replaced.
+    match (base, overlay) {
+        (Value::Mapping(mut base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                let merged = match base.remove(&key) {
+                    Some(existing) => merge_settings(existing, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Mapping(base)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+fn merge_materials(base: Value, overlay: Value) -> Value {
+    // Materials are a list of single-key maps from their name to their settings. A material
+    // replaces an earlier one of the same name where it was, and new materials go at the end.
+    match (base, overlay) {
+        (Value::Sequence(mut base), Value::Sequence(overlay)) => {
+            for material in overlay {
+                let name = material.as_mapping().and_then(|mapping| mapping.keys().next()).cloned();
+                let existing = name.and_then(|name| {
+                    base.iter().position(|earlier| earlier.get(&name).is_some())
+                });
+                match existing {
+                    Some(position) => {
+                        base[position] = material;
+                    }
+                    None => base.push(material),
+                }
+            }
+            Value::Sequence(base)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+fn substitute(value: &mut Value, vars: &Mapping, depth: usize) -> Result<(), String> {
+    // Replace ${name} in every string inside value.
+    match value {
+        Value::String(text) => {
+            if let Some(replacement) = substitute_text(text, vars, depth)? {
+                *value = replacement;
+            }
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                substitute(item, vars, depth)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                substitute(item, vars, depth)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn substitute_text(text: &str, vars: &Mapping, depth: usize) -> Result<Option<Value>, String> {
+    // The value replacing text, or None if it has nothing to substitute.
+    if !text.contains("${") {
+        return Ok(None);
+    }
+    if depth >= MAX_DEPTH {
+        return Err(format!("variables nested too deeply in '{}'", text));
+    }
+
+    // A string that is only a variable becomes the variable's value.
+    if let Some(name) = text.strip_prefix("${").and_then(|rest| rest.strip_suffix('}')) {
+        if !name.contains(['$', '{', '}']) {
+            return lookup(name, vars, depth).map(Some);
+        }
+    }
+
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or(format!("missing '}}' in '{}'", text))?;
+        let name = &rest[start + 2..start + end];
+        match lookup(name, vars, depth)? {
+            Value::String(value) => result.push_str(&value),
+            Value::Number(value) => result.push_str(&value.to_string()),
+            Value::Bool(value) => result.push_str(&value.to_string()),
+            _ => {
+                return Err(format!("variable '{}' can't be used inside text", name));
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(Some(Value::String(result)))
+}
+
+fn lookup(name: &str, vars: &Mapping, depth: usize) -> Result<Value, String> {
+    // Variables may themselves refer to variables.
+    if let Some(value) = vars.get(name) {
+        let mut value = value.clone();
+        substitute(&mut value, vars, depth + 1)?;
+        return Ok(value);
+    }
+    std::env::var(name)
+        .map(Value::String)
+        .map_err(|_| format!("unknown variable '{}'", name))
+}
+
+fn remove_key(scene: &mut Value, key: &str) -> Option<Value> {
+    scene.as_mapping_mut()?.remove(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene_directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(
+            format!("rt-scene-file-{}-{}", name, std::process::id())
+        );
+        for (file, content) in files {
+            let path = directory.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        directory
+    }
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn including_file_takes_precedence() {
+        let directory = scene_directory("merge", &[
+            (
+                "lib/common.yaml",
+                "camera: { vfov: 30, image_width: 100 }
+materials:
+  - ground: { type: Lambertian, colour: [0.5, 0.5, 0.5] }
+  - metal: { type: Metal, colour: [0.8, 0.8, 0.8] }
+objects:
+  - { type: Sphere, radius: 1.0, mat: ground }
+filename: common",
+            ),
+            (
+                "main.yaml",
+                "include: lib/common.yaml
+camera: { vfov: 40 }
+materials:
+  - ground: { type: Lambertian, colour: [0.1, 0.2, 0.3] }
+  - glass: { type: Pbr, colour: [1.0, 1.0, 1.0], transmission: 1.0 }
+objects:
+  - { type: Sphere, radius: 2.0, mat: glass }",
+            ),
+        ]);
+        let scene = load(directory.join("main.yaml").to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(scene["camera"], yaml("{ vfov: 40, image_width: 100 }"));
+        assert_eq!(
+            scene["materials"],
+            yaml(
+                "[ground: { type: Lambertian, colour: [0.1, 0.2, 0.3] },
+                 metal: { type: Metal, colour: [0.8, 0.8, 0.8] },
+                 glass: { type: Pbr, colour: [1.0, 1.0, 1.0], transmission: 1.0 }]"
+            )
+        );
+        let radii: Vec<f64> = scene["objects"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|object| object["radius"].as_f64().unwrap())
+            .collect();
+        assert_eq!(radii, [1.0, 2.0]);
+        assert_eq!(scene["filename"], yaml("common"));
+    }
+
+    #[test]
+    fn includes_that_loop_are_rejected() {
+        let directory = scene_directory("loop", &[
+            ("a.yaml", "include: b.yaml"),
+            ("b.yaml", "include: a.yaml"),
+        ]);
+        let result = load(directory.join("a.yaml").to_str().unwrap());
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(result.unwrap_err().to_string().contains("includes itself"));
+    }
+
+    #[test]
+    fn included_asset_paths_are_relative_to_their_file() {
+        let directory = scene_directory("assets", &[
+            (
+                "lib/nested/maps.yaml",
+                "materials:
+  - bumpy: { type: Metal, colour: [0.8, 0.8, 0.8], bump_map: bump.ppm }",
+            ),
+            (
+                "lib/assets.yaml",
+                "include: nested/maps.yaml
+materials:
+  - tiles: { type: Lambertian, colour: [0.5, 0.5, 0.5], normal_map: textures/tiles.ppm }
+  - fixed: { type: Lambertian, colour: [0.5, 0.5, 0.5], normal_map: /maps/fixed.ppm }
+  - chosen: { type: Lambertian, colour: [0.5, 0.5, 0.5], normal_map: \"${MAPS}/a.ppm\" }
+objects:
+  - { type: Volume, grid: smoke.rtgrid }",
+            ),
+            ("main.yaml", "include: lib/assets.yaml\nvars: { MAPS: /elsewhere }"),
+        ]);
+        let scene = load(directory.join("main.yaml").to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let lib = directory.join("lib");
+        let material = |index: usize, name: &str, key: &str| {
+            scene["materials"][index][name][key].as_str().unwrap().to_string()
+        };
+        let path = |path: PathBuf| path.to_string_lossy().into_owned();
+        assert_eq!(material(0, "bumpy", "bump_map"), path(lib.join("nested").join("bump.ppm")));
+        assert_eq!(material(1, "tiles", "normal_map"), path(lib.join("textures/tiles.ppm")));
+        assert_eq!(material(2, "fixed", "normal_map"), "/maps/fixed.ppm");
+        assert_eq!(material(3, "chosen", "normal_map"), "/elsewhere/a.ppm");
+        assert_eq!(scene["objects"][0]["grid"].as_str().unwrap(), path(lib.join("smoke.rtgrid")));
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let vars = yaml("{ radius: 0.5, grey: [0.5, 0.5, 0.5], name: ball, label: \"${name}-1\" }");
+        let vars = vars.as_mapping().unwrap();
+        let mut scene = yaml(
+            "{ radius: \"${radius}\", colour: \"${grey}\", title: \"a ${name} of ${radius}\",
+               nested: [\"${label}\"] }"
+        );
+        substitute(&mut scene, vars, 0).unwrap();
+        assert_eq!(
+            scene,
+            yaml("{ radius: 0.5, colour: [0.5, 0.5, 0.5], title: a ball of 0.5, nested: [ball-1] }")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_variables() {
+        let vars = yaml("{ grey: [0.5, 0.5, 0.5], me: \"${me}\" }");
+        let vars = vars.as_mapping().unwrap();
+        for text in ["${rt_test_unset_variable}", "a ${grey}", "${me}", "a ${radius"] {
+            assert!(substitute(&mut Value::from(text), vars, 0).is_err(), "{}", text);
+        }
+    }
+}
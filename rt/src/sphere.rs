@@ -1,4 +1,4 @@
-use std::{ rc::Rc, f64::consts::PI };
+use std::{ sync::Arc, f64::consts::PI };
 
 use crate::{
     vector3::{ Point3, dot, Vector3 },
@@ -6,17 +6,18 @@ use crate::{
     interval::Interval,
     material::Material,
     aabb::Aabb,
+    stats,
 };
 
 pub struct Sphere {
     centre: Point3,
     radius: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
     bbox: Aabb,
 }
 
 impl Sphere {
-    pub fn new(centre: Point3, radius: f64, mat: Rc<dyn Material>) -> Self {
+    pub fn new(centre: Point3, radius: f64, mat: Arc<dyn Material>) -> Self {
         let rvec = Vector3::new(radius, radius, radius);
         let bbox = Aabb::from_points(centre - rvec, centre + rvec);
         Sphere { centre, radius, mat, bbox }
@@ -36,6 +37,20 @@ impl Sphere {
         *u = phi / (2.0 * PI);
         *v = theta / PI;
     }
+
+    fn get_sphere_tangents(&self, p: Point3) -> (Vector3, Vector3) {
+        // p: a given point on the sphere of radius one, centered at the origin.
+        // Returns the derivatives of the surface position with respect to the u and v of
+        // get_sphere_uv, which are degenerate at the poles.
+        let x = p.x();
+        let y = p.y();
+        let z = p.z();
+        let sin_theta = (1.0 - y * y).max(1e-8).sqrt();
+
+        let dpdu = 2.0 * PI * self.radius * Vector3::new(z, 0.0, -x);
+        let dpdv = PI * self.radius * Vector3::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta);
+        (dpdu, dpdv)
+    }
 }
 
 impl Hittable for Sphere {
@@ -45,6 +60,8 @@ impl Hittable for Sphere {
         ray_t: Interval,
         rec: &mut crate::hittable::HitRecord
     ) -> bool {
+        stats::count_primitive_test();
+
         //The following is a simplified quadratic formula
         let oc = r.origin() - self.centre;
         let a = r.direction().length_squared();
@@ -71,12 +88,13 @@ impl Hittable for Sphere {
         let outward_normal = (rec.p - self.centre) / self.radius;
         rec.set_face_normal(r, &outward_normal);
         Sphere::get_sphere_uv(outward_normal, &mut rec.u, &mut rec.v);
+        (rec.dpdu, rec.dpdv) = self.get_sphere_tangents(outward_normal);
         rec.mat = self.mat.clone();
 
-        return true;
+        true
     }
 
     fn bounding_box(&self) -> Aabb {
-        return self.bbox;
+        self.bbox
     }
 }
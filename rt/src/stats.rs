@@ -0,0 +1,355 @@
+use std::{ cell::RefCell, io::{ self, Write }, sync::{ Mutex, MutexGuard }, time::Duration };
+
+use serde_json::json;
+
+// Counts of the work done while rendering. Each thread keeps its own counts, which are added
+// to the render's totals with take when the thread finishes.
+#[derive(Debug, Default, Clone)]
+pub struct Counters {
+    pub camera_rays: u64,
+    pub scatter_rays: u64,
+    pub shadow_rays: u64, // Always zero while the sky is the only light
+    pub primitive_tests: u64,
+    pub bbox_tests: u64,
+    pub escaped: Vec<u64>, // Paths that escaped to the sky, by their number of bounces
+    pub absorbed: Vec<u64>, // Paths absorbed by a material, by their number of bounces
+    pub bounce_limit: u64, // Paths cut off by max_depth, or by a random walk's max_steps
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PathEnd {
+    Sky,
+    Absorbed,
+    BounceLimit,
+}
+
+thread_local! {
+    static COUNTERS: RefCell<Counters> = const {
+        RefCell::new(Counters {
+            camera_rays: 0,
+            scatter_rays: 0,
+            shadow_rays: 0,
+            primitive_tests: 0,
+            bbox_tests: 0,
+            escaped: Vec::new(),
+            absorbed: Vec::new(),
+            bounce_limit: 0,
+        })
+    };
+}
+
+pub fn count_ray(camera_ray: bool) {
+    COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        if camera_ray {
+            counters.camera_rays += 1;
+        } else {
+            counters.scatter_rays += 1;
+        }
+    });
+}
+
+pub fn count_primitive_test() {
+    COUNTERS.with(|counters| {
+        counters.borrow_mut().primitive_tests += 1;
+    });
+}
+
+pub fn count_bbox_test() {
+    COUNTERS.with(|counters| {
+        counters.borrow_mut().bbox_tests += 1;
+    });
+}
+
+pub fn count_path(end: PathEnd, bounces: i32) {
+    COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        let bounces = bounces.max(0) as usize;
+        let histogram = match end {
+            PathEnd::Sky => &mut counters.escaped,
+            PathEnd::Absorbed => &mut counters.absorbed,
+            PathEnd::BounceLimit => {
+                counters.bounce_limit += 1;
+                return;
+            }
+        };
+        if histogram.len() <= bounces {
+            histogram.resize(bounces + 1, 0);
+        }
+        histogram[bounces] += 1;
+    });
+}
+
+pub fn rays_traced() -> u64 {
+    // Rays this thread has traced since it last took its counts.
+    COUNTERS.with(|counters| counters.borrow().total_rays())
+}
+
+pub fn take() -> Counters {
+    // This thread's counts since it last took them.
+    COUNTERS.with(|counters| std::mem::take(&mut *counters.borrow_mut()))
+}
+
+impl Counters {
+    pub fn add(&mut self, other: &Counters) {
+        self.camera_rays += other.camera_rays;
+        self.scatter_rays += other.scatter_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.primitive_tests += other.primitive_tests;
+        self.bbox_tests += other.bbox_tests;
+        self.bounce_limit += other.bounce_limit;
+        for (histogram, other) in [
+            (&mut self.escaped, &other.escaped),
+            (&mut self.absorbed, &other.absorbed),
+        ] {
+            if histogram.len() < other.len() {
+                histogram.resize(other.len(), 0);
+            }
+            for (count, other_count) in histogram.iter_mut().zip(other) {
+                *count += other_count;
+            }
+        }
+    }
+
+    fn total_rays(&self) -> u64 {
+        self.camera_rays + self.scatter_rays + self.shadow_rays
+    }
+
+    fn average_depth(&self) -> f64 {
+        // The mean number of bounces of a path, counting paths cut off by max_depth at the
+        // bounce they were cut off at.
+        let paths = self.camera_rays.max(1) as f64;
+        ((self.scatter_rays + self.bounce_limit) as f64) / paths
+    }
+
+    fn bounce_rows(&self, max_depth: i32) -> Vec<(usize, u64, u64, u64)> {
+        // The number of paths that escaped, were absorbed or were cut off, by bounces, for
+        // every number of bounces that any path ended at.
+        let limit = max_depth.max(0) as usize;
+        let rows = self.escaped.len().max(self.absorbed.len()).max(limit + 1);
+        (0..rows)
+            .map(|bounces| {
+                let escaped = self.escaped.get(bounces).copied().unwrap_or(0);
+                let absorbed = self.absorbed.get(bounces).copied().unwrap_or(0);
+                let cut_off = if bounces == limit { self.bounce_limit } else { 0 };
+                (bounces, escaped, absorbed, cut_off)
+            })
+            .filter(|(_, escaped, absorbed, cut_off)| escaped + absorbed + cut_off > 0)
+            .collect()
+    }
+}
+
+// Where the time went in a render, and what it did.
+#[derive(Debug, Default)]
+pub struct RenderStats {
+    pub parse: Duration, // Reading the scene file and resolving includes and variables
+    pub build: Duration, // Building the world's materials and objects
+    pub render: Duration,
+    pub write: Duration, // Denoising and writing the images
+    pub threads: usize,
+    pub max_depth: i32,
+    pub counters: Counters,
+}
+
+impl RenderStats {
+    fn total(&self) -> Duration {
+        self.parse + self.build + self.render + self.write
+    }
+
+    fn rays_per_second(&self) -> f64 {
+        (self.counters.total_rays() as f64) / self.render.as_secs_f64().max(1e-9)
+    }
+
+    pub fn print(&self) {
+        // On stderr below the progress, so the output of a render can be piped. A closed
+        // stderr isn't worth stopping for.
+        let mut stderr = io::stderr().lock();
+        let _ = writeln!(stderr).and_then(|_| self.write_summary(&mut stderr));
+    }
+
+    pub fn write_summary(&self, out: &mut impl Write) -> io::Result<()> {
+        let counters = &self.counters;
+        writeln!(out, "Render statistics:")?;
+        writeln!(
+            out,
+            "  Time: parse {:.3}s, build {:.3}s, render {:.3}s, write {:.3}s, total {:.3}s",
+            self.parse.as_secs_f64(),
+            self.build.as_secs_f64(),
+            self.render.as_secs_f64(),
+            self.write.as_secs_f64(),
+            self.total().as_secs_f64()
+        )?;
+        writeln!(
+            out,
+            "  Rays: {} camera, {} scatter, {} shadow, {} in total",
+            counters.camera_rays,
+            counters.scatter_rays,
+            counters.shadow_rays,
+            counters.total_rays()
+        )?;
+        writeln!(out, "  Rays per second: {:.0}", self.rays_per_second())?;
+        writeln!(out, "  Threads: {}", self.threads)?;
+        writeln!(
+            out,
+            "  Tests: {} primitive, {} bounding box",
+            counters.primitive_tests,
+            counters.bbox_tests
+        )?;
+        writeln!(out, "  Average path depth: {:.2} bounces", counters.average_depth())?;
+        writeln!(
+            out,
+            "  {:>8} {:>12} {:>12} {:>12}",
+            "Bounces",
+            "Sky",
+            "Absorbed",
+            "Bounce limit"
+        )?;
+        for (bounces, escaped, absorbed, cut_off) in counters.bounce_rows(self.max_depth) {
+            writeln!(out, "  {:>8} {:>12} {:>12} {:>12}", bounces, escaped, absorbed, cut_off)?;
+        }
+        writeln!(out)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let counters = &self.counters;
+        let bounces: Vec<serde_json::Value> = counters
+            .bounce_rows(self.max_depth)
+            .into_iter()
+            .map(|(bounces, escaped, absorbed, cut_off)| {
+                json!({
+                    "bounces": bounces,
+                    "sky": escaped,
+                    "absorbed": absorbed,
+                    "bounce_limit": cut_off,
+                })
+            })
+            .collect();
+        json!({
+            "time": {
+                "parse": self.parse.as_secs_f64(),
+                "build": self.build.as_secs_f64(),
+                "render": self.render.as_secs_f64(),
+                "write": self.write.as_secs_f64(),
+                "total": self.total().as_secs_f64(),
+            },
+            "rays": {
+                "camera": counters.camera_rays,
+                "scatter": counters.scatter_rays,
+                "shadow": counters.shadow_rays,
+                "total": counters.total_rays(),
+                "per_second": self.rays_per_second(),
+            },
+            "threads": self.threads,
+            "tests": {
+                "primitive": counters.primitive_tests,
+                "bounding_box": counters.bbox_tests,
+            },
+            "average_path_depth": counters.average_depth(),
+            "max_depth": self.max_depth,
+            "bounces": bounces,
+        })
+    }
+
+    pub fn write_json(&self, filename: &str) -> std::io::Result<()> {
+        let content = serde_json::to_string_pretty(&self.to_json())? + "\n";
+        std::fs::write(format!("{}_stats.json", filename), content)
+    }
+}
+
+// A render's statistics, added to by each of its threads. A copy of a camera starts its own.
+#[derive(Debug, Default)]
+pub struct SharedStats(Mutex<RenderStats>);
+
+impl SharedStats {
+    pub fn lock(&self) -> MutexGuard<'_, RenderStats> {
+        self.0.lock().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut RenderStats {
+        self.0.get_mut().unwrap()
+    }
+}
+
+impl Clone for SharedStats {
+    fn clone(&self) -> Self {
+        SharedStats::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ render, RenderSettings, Scene };
+
+    #[test]
+    fn renders_count_a_camera_ray_per_sample() {
+        // 8 by 6 pixels with 3 samples each, on two threads.
+        let mut scene = Scene::from_yaml_str(
+            "stats.yaml",
+            "
+camera:
+  image_width: 8
+  aspect_ratio: 1.3333333333333333
+  samples_per_pixel: 3
+  max_depth: 4
+materials:
+  - ground: { type: Lambertian, colour: [0.5, 0.5, 0.5] }
+objects:
+  - { type: Sphere, centre: [0.0, 0.0, -1.0], radius: 0.5, mat: ground }
+"
+        ).unwrap();
+        let path = std::env::temp_dir().join(format!("rt-stats-{}", std::process::id()));
+        scene.filename = path.to_str().unwrap().to_string();
+        let settings = RenderSettings {
+            seed: Some(3),
+            threads: Some(2),
+            stats_json: true,
+            ..RenderSettings::default()
+        };
+        render(&scene, &settings).unwrap();
+        let json_path = scene.filename.clone() + "_stats.json";
+        let stats: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+        std::fs::remove_file(&json_path).unwrap();
+
+        assert_eq!(stats["rays"]["camera"], 8 * 6 * 3);
+        assert_eq!(stats["threads"], 2);
+        // Every camera ray's path ends exactly once.
+        let ended: u64 = stats["bounces"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| {
+                ["sky", "absorbed", "bounce_limit"]
+                    .iter()
+                    .map(|end| row[end].as_u64().unwrap())
+                    .sum::<u64>()
+            })
+            .sum();
+        assert_eq!(ended, 8 * 6 * 3);
+    }
+
+    #[test]
+    fn summary_lists_the_counts() {
+        let stats = RenderStats {
+            threads: 4,
+            max_depth: 2,
+            counters: Counters {
+                camera_rays: 10,
+                scatter_rays: 6,
+                escaped: vec![4, 3],
+                absorbed: vec![0, 1],
+                bounce_limit: 2,
+                ..Counters::default()
+            },
+            ..RenderStats::default()
+        };
+        let mut summary = Vec::new();
+        stats.write_summary(&mut summary).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        assert!(summary.contains("Rays: 10 camera, 6 scatter, 0 shadow, 16 in total"));
+        assert!(summary.contains("Average path depth: 0.80 bounces"));
+        assert!(summary.contains("         1            3            1            0"));
+        assert!(summary.contains("         2            0            0            2"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{ collections::VecDeque, sync::{ Condvar, Mutex } };
+
+use serde::Deserialize;
+
+use crate::{ film::{ Film, Region }, filter::Filter };
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum TileOrder {
+    Scanline, // Rows of tiles from the top left
+    Spiral, // Outwards from the centre of the image
+    Hilbert, // Along a Hilbert curve, which keeps consecutive tiles next to each other
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("unknown tile order '{}'", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Hilbert => "hilbert",
+        }
+    }
+}
+
+// One tile's share of a render pass: how many samples each of its pixels gets, and the index of
+// the first of them, or -1 for pixels that get none this pass.
+pub struct TileJob {
+    pub tile: Region,
+    pub samples: i32,
+    pub first_samples: Vec<i32>, // Row by row over the tile
+}
+
+// The film a tile was rendered into, which covers the tile and the pixels around it that its
+// samples splat into, and the position of its top left pixel in the image.
+pub type TileResult = ((i32, i32), Film);
+
+// The tiles of a pass still waiting to be rendered, shared between the local threads and the
+// connections to workers. A worker that drops out gives its tiles back for the others to take.
+pub struct TileQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+struct QueueState {
+    pending: VecDeque<usize>,
+    in_progress: usize,
+}
+
+impl TileQueue {
+    pub fn new(pending: impl IntoIterator<Item = usize>) -> Self {
+        TileQueue {
+            state: Mutex::new(QueueState { pending: pending.into_iter().collect(), in_progress: 0 }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn take(&self) -> Option<usize> {
+        // The next tile to render, or None once every tile is done. While the queue is empty
+        // but tiles are still being rendered, waits in case one of them is given back.
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(index) = state.pending.pop_front() {
+                state.in_progress += 1;
+                return Some(index);
+            }
+            if state.in_progress == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    pub fn try_take(&self) -> Option<usize> {
+        // The next tile to render, without waiting if there's none right now.
+        let mut state = self.state.lock().unwrap();
+        let index = state.pending.pop_front()?;
+        state.in_progress += 1;
+        Some(index)
+    }
+
+    pub fn finish(&self) {
+        // One of the tiles taken has been rendered.
+        self.state.lock().unwrap().in_progress -= 1;
+        self.changed.notify_all();
+    }
+
+    pub fn give_back(&self, index: usize) {
+        // One of the tiles taken couldn't be rendered, so someone else has to.
+        let mut state = self.state.lock().unwrap();
+        state.in_progress -= 1;
+        state.pending.push_back(index);
+        self.changed.notify_all();
+    }
+}
+
+pub fn tiles(width: i32, height: i32, tile_size: i32, order: TileOrder) -> Vec<Region> {
+    // Splits the image into tiles of tile_size square, smaller at the right and bottom edges,
+    // in the order they should be rendered.
+    let tile_size = tile_size.max(1);
+    let columns = (width + tile_size - 1) / tile_size;
+    let rows = (height + tile_size - 1) / tile_size;
+
+    let positions = match order {
+        TileOrder::Scanline => {
+            let mut positions = Vec::new();
+            for row in 0..rows {
+                for column in 0..columns {
+                    positions.push((column, row));
+                }
+            }
+            positions
+        }
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => hilbert(columns, rows),
+    };
+
+    positions
+        .into_iter()
+        .map(|(column, row)| Region {
+            x0: column * tile_size,
+            y0: row * tile_size,
+            x1: ((column + 1) * tile_size).min(width),
+            y1: ((row + 1) * tile_size).min(height),
+        })
+        .collect()
+}
+
+pub fn splat_bounds(tile: Region, filter: &Filter, width: i32, height: i32) -> Region {
+    // The tile and the pixels around it that its samples splat into, inside the image.
+    let margin = filter.radius.ceil() as i32;
+    let bounds = Region {
+        x0: tile.x0 - margin,
+        y0: tile.y0 - margin,
+        x1: tile.x1 + margin,
+        y1: tile.y1 + margin,
+    };
+    bounds.clamp(width, height).unwrap_or(tile)
+}
+
+fn spiral(columns: i32, rows: i32) -> Vec<(i32, i32)> {
+    // Walks a square spiral out from the centre tile (right, down, left 2, up 2, right 3, ...),
+    // keeping the positions that fall inside the grid.
+    let total = (columns * rows) as usize;
+    let mut positions = Vec::with_capacity(total);
+    let (mut x, mut y) = ((columns - 1) / 2, (rows - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut direction = 0;
+    let mut run = 1;
+
+    positions.push((x, y));
+    while positions.len() < total {
+        // Each run length is used twice before it grows.
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction];
+            for _ in 0..run {
+                x += dx;
+                y += dy;
+                if x >= 0 && x < columns && y >= 0 && y < rows {
+                    positions.push((x, y));
+                }
+            }
+            direction = (direction + 1) % 4;
+        }
+        run += 1;
+    }
+    positions
+}
+
+fn hilbert(columns: i32, rows: i32) -> Vec<(i32, i32)> {
+    // Follows the Hilbert curve over the smallest power of two square covering the grid,
+    // keeping the positions that fall inside the grid.
+    let n = (columns.max(rows).max(1) as u32).next_power_of_two() as i32;
+    let mut positions = Vec::with_capacity((columns * rows) as usize);
+    for d in 0..n * n {
+        let (x, y) = hilbert_position(n, d);
+        if x < columns && y < rows {
+            positions.push((x, y));
+        }
+    }
+    positions
+}
+
+fn hilbert_position(n: i32, d: i32) -> (i32, i32) {
+    // The position of the d-th point along the Hilbert curve filling an n by n square.
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covers_every_pixel_once(width: i32, height: i32, tiles: &[Region]) -> bool {
+        let mut covered = vec![0; (width * height) as usize];
+        for tile in tiles {
+            for j in tile.y0..tile.y1 {
+                for i in tile.x0..tile.x1 {
+                    covered[(j * width + i) as usize] += 1;
+                }
+            }
+        }
+        covered.iter().all(|count| *count == 1)
+    }
+
+    #[test]
+    fn every_order_covers_the_image() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            for (width, height, tile_size) in [(100, 60, 16), (7, 33, 8), (64, 64, 32), (1, 1, 4)] {
+                let tiles = tiles(width, height, tile_size, order);
+                assert!(covers_every_pixel_once(width, height, &tiles), "{:?}", order);
+            }
+        }
+    }
+
+    #[test]
+    fn scanline_goes_row_by_row() {
+        let tiles = tiles(40, 20, 16, TileOrder::Scanline);
+        let corners: Vec<(i32, i32)> = tiles.iter().map(|tile| (tile.x0, tile.y0)).collect();
+        assert_eq!(corners, [(0, 0), (16, 0), (32, 0), (0, 16), (16, 16), (32, 16)]);
+        assert_eq!(tiles[2], Region { x0: 32, y0: 0, x1: 40, y1: 16 });
+    }
+
+    #[test]
+    fn spiral_starts_in_the_centre() {
+        assert_eq!(
+            spiral(3, 3),
+            [(1, 1), (2, 1), (2, 2), (1, 2), (0, 2), (0, 1), (0, 0), (1, 0), (2, 0)]
+        );
+    }
+
+    #[test]
+    fn hilbert_steps_to_neighbouring_tiles() {
+        for (columns, rows) in [(4, 4), (8, 8)] {
+            let positions = hilbert(columns, rows);
+            assert_eq!(positions.len(), (columns * rows) as usize);
+            for pair in positions.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert_eq!((a.0 - b.0).abs() + (a.1 - b.1).abs(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn queue_hands_out_given_back_tiles() {
+        let queue = TileQueue::new([0, 1]);
+        assert_eq!(queue.take(), Some(0));
+        assert_eq!(queue.take(), Some(1));
+        assert_eq!(queue.try_take(), None);
+        queue.give_back(0);
+        assert_eq!(queue.try_take(), Some(0));
+        queue.finish();
+        queue.finish();
+        assert_eq!(queue.take(), None);
+    }
+}
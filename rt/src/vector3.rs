@@ -2,8 +2,6 @@ use std::ops::{ AddAssign, MulAssign, DivAssign, Add, Sub, Mul, Div, Neg };
 
 use serde::Deserialize;
 
-use crate::rtweekend::{ random_f64, random_f64_range };
-
 #[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
 pub struct Vector3 {
     e: [f64; 3],
@@ -14,10 +12,6 @@ impl Vector3 {
         Vector3 { e: [e0, e1, e2] }
     }
 
-    pub fn default() -> Self {
-        Vector3 { e: [0.0, 0.0, 0.0] }
-    }
-
     pub fn x(&self) -> f64 {
         self.e[0]
     }
@@ -35,11 +29,11 @@ impl Vector3 {
     }
 
     pub fn length(&self) -> f64 {
-        return self.length_squared().sqrt();
+        self.length_squared().sqrt()
     }
 
     pub fn length_squared(&self) -> f64 {
-        return self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2];
+        self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]
     }
 
     pub fn near_zero(&self) -> bool {
@@ -47,18 +41,6 @@ impl Vector3 {
         let s = 1e-8;
         self.e[0].abs() < s && self.e[1].abs() < s && self.e[2].abs() < s
     }
-
-    pub fn random() -> Vector3 {
-        return Vector3::new(random_f64(), random_f64(), random_f64());
-    }
-
-    pub fn random_range(min: f64, max: f64) -> Vector3 {
-        return Vector3::new(
-            random_f64_range(min, max),
-            random_f64_range(min, max),
-            random_f64_range(min, max)
-        );
-    }
 }
 
 impl AddAssign for Vector3 {
@@ -151,6 +133,16 @@ impl Div<f64> for Vector3 {
     }
 }
 
+impl Div for Vector3 {
+    type Output = Vector3;
+
+    fn div(self, other: Vector3) -> Vector3 {
+        Vector3 {
+            e: [self.e[0] / other.e[0], self.e[1] / other.e[1], self.e[2] / other.e[2]],
+        }
+    }
+}
+
 impl Neg for Vector3 {
     type Output = Vector3;
 
@@ -179,30 +171,29 @@ pub fn unit_vector(v: Vector3) -> Vector3 {
     v / v.length()
 }
 
-pub fn random_in_unit_sphere() -> Vector3 {
-    loop {
-        let p = Vector3::random_range(-1.0, 1.0);
-        if p.length_squared() < 1.0 {
-            return p;
-        }
-    }
+pub fn sample_unit_vector(u: (f64, f64)) -> Vector3 {
+    // Maps a point in the unit square to a uniformly distributed direction.
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * u.1;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_unit_vector() -> Vector3 {
-    return unit_vector(random_in_unit_sphere());
+pub fn reflect(v: Vector3, n: Vector3) -> Vector3 {
+    v - 2.0 * dot(v, n) * n
 }
 
-// pub fn random_on_hemisphere(normal: Vector3) -> Vector3 {
-//     let on_unit_sphere = random_unit_vector();
-//     if
-//         dot(on_unit_sphere, normal) > 0.0 // In the same hemisphere as the normal
-//     {
-//         return on_unit_sphere;
-//     } else {
-//         return -on_unit_sphere;
-//     }
-// }
+pub fn refract(uv: Vector3, n: Vector3, etai_over_etat: f64) -> Vector3 {
+    let cos_theta = dot(-uv, n).min(1.0);
+    let r_out_perp = etai_over_etat * (uv + cos_theta * n);
+    let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
+    r_out_perp + r_out_parallel
+}
 
-pub fn reflect(v: Vector3, n: Vector3) -> Vector3 {
-    return v - 2.0 * dot(v, n) * n;
+pub fn orthonormal_basis(n: Vector3) -> (Vector3, Vector3) {
+    // Returns two unit vectors that, together with the unit vector n, form an orthonormal basis.
+    let a = if n.x().abs() > 0.9 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let s = unit_vector(cross(n, a));
+    let t = cross(n, s);
+    (s, t)
 }
@@ -0,0 +1,231 @@
+use std::{ fs, sync::Arc };
+
+use crate::{
+    aabb::Aabb,
+    hittable::{ Hittable, HitRecord },
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    rtweekend::random_f64,
+    vector3::{ Point3, Vector3 },
+    stats,
+};
+
+// A dense grid of density values stored in a `.rtgrid` file. The file starts with a single
+// ASCII header line
+//
+//     RTGRID <nx> <ny> <nz> <minx> <miny> <minz> <maxx> <maxy> <maxz>
+//
+// followed by nx * ny * nz little-endian f32 densities, with x varying fastest and z slowest.
+// The header's min/max corners give the world-space bounds of the grid.
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    bounds: Aabb,
+    data: Vec<f64>,
+    max_density: f64,
+}
+
+impl VoxelGrid {
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let bytes = fs::read(path)?;
+
+        let header_end = bytes
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(format!("'{}' is missing the RTGRID header line", path))?;
+        let header = std::str::from_utf8(&bytes[..header_end])?;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 10 || fields[0] != "RTGRID" {
+            return Err(format!("'{}' has an invalid RTGRID header", path).into());
+        }
+
+        let nx: usize = fields[1].parse()?;
+        let ny: usize = fields[2].parse()?;
+        let nz: usize = fields[3].parse()?;
+        let mut corners = [0.0; 6];
+        for (corner, field) in corners.iter_mut().zip(&fields[4..]) {
+            *corner = field.parse()?;
+        }
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(format!("'{}' has an empty grid", path).into());
+        }
+
+        let body = &bytes[header_end + 1..];
+        if body.len() != nx * ny * nz * 4 {
+            return Err(
+                format!(
+                    "'{}' should hold {} densities but holds {} bytes",
+                    path,
+                    nx * ny * nz,
+                    body.len()
+                ).into()
+            );
+        }
+
+        let data: Vec<f64> = body
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f64)
+            .collect();
+
+        let bounds = Aabb::from_points(
+            Point3::new(corners[0], corners[1], corners[2]),
+            Point3::new(corners[3], corners[4], corners[5])
+        );
+
+        Ok(VoxelGrid::new([nx, ny, nz], bounds, data)?)
+    }
+
+    pub fn new(size: [usize; 3], bounds: Aabb, data: Vec<f64>) -> Result<Self, String> {
+        // A grid of nx * ny * nz densities over the bounds, with x varying fastest and z slowest.
+        // Negative densities are taken as zero.
+        let [nx, ny, nz] = size;
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err("the grid is empty".to_string());
+        }
+        if data.len() != nx * ny * nz {
+            return Err(format!("the grid should hold {} densities", nx * ny * nz));
+        }
+        let data: Vec<f64> = data
+            .into_iter()
+            .map(|density| density.max(0.0))
+            .collect();
+        let max_density = data.iter().cloned().fold(0.0, f64::max);
+        Ok(VoxelGrid { nx, ny, nz, bounds, data, max_density })
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.data[(z * self.ny + y) * self.nx + x]
+    }
+
+    fn density(&self, p: Point3) -> f64 {
+        // Trilinearly interpolate the density at p, treating values as sampled at voxel centres.
+        let coord = |v: f64, axis: Interval, n: usize| -> (usize, usize, f64) {
+            let g = ((v - axis.min) / axis.size()) * (n as f64) - 0.5;
+            let g = g.clamp(0.0, (n - 1) as f64);
+            let i0 = g.floor() as usize;
+            let i1 = (i0 + 1).min(n - 1);
+            (i0, i1, g - (i0 as f64))
+        };
+
+        let (x0, x1, fx) = coord(p.x(), self.bounds.x, self.nx);
+        let (y0, y1, fy) = coord(p.y(), self.bounds.y, self.ny);
+        let (z0, z1, fz) = coord(p.z(), self.bounds.z, self.nz);
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx);
+
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+}
+
+pub struct HeterogeneousMedium {
+    grid: VoxelGrid,
+    density_scale: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(grid: VoxelGrid, density_scale: f64, phase_function: Arc<dyn Material>) -> Self {
+        HeterogeneousMedium { grid, density_scale, phase_function }
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        stats::count_primitive_test();
+        // Delta tracking: take exponential steps against the majorant (the grid's maximum
+        // density) and accept a tentative collision with probability density / majorant.
+        let majorant = self.grid.max_density * self.density_scale;
+        if majorant <= 0.0 {
+            return false;
+        }
+
+        let segment = match self.grid.bounds.clip(r, ray_t) {
+            Some(segment) => segment,
+            None => {
+                return false;
+            }
+        };
+
+        let ray_length = r.direction().length();
+        let mut t = segment.min;
+        loop {
+            t -= (1.0 - random_f64()).ln() / (majorant * ray_length);
+            if t >= segment.max {
+                return false;
+            }
+
+            let p = r.at(t);
+            if self.grid.density(p) * self.density_scale > random_f64() * majorant {
+                rec.t = t;
+                rec.p = p;
+                rec.normal = Vector3::new(1.0, 0.0, 0.0); // arbitrary
+                rec.front_face = true; // also arbitrary
+                rec.dpdu = Vector3::default();
+                rec.dpdv = Vector3::default();
+                rec.mat = self.phase_function.clone();
+                return true;
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.grid.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ material::HenyeyGreenstein, colour::Colour, rtweekend::start_sample_stream };
+
+    fn unit_cube(density: f64) -> HeterogeneousMedium {
+        let bounds = Aabb::from_points(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let grid = VoxelGrid::new([2, 2, 2], bounds, vec![density; 8]).unwrap();
+        let phase_function = Arc::new(HenyeyGreenstein::new(Colour::new(1.0, 1.0, 1.0), 0.0));
+        HeterogeneousMedium::new(grid, 1.0, phase_function)
+    }
+
+    fn through_cube() -> Ray {
+        Ray::new(Point3::new(-1.0, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn delta_tracking_matches_beer_lambert() {
+        let medium = unit_cube(1.5);
+        let trials = 20000;
+        let mut passed = 0;
+        for trial in 0..trials {
+            start_sample_stream(2, 0, trial);
+            let mut rec = HitRecord::default();
+            if !medium.hit(&through_cube(), Interval::new(0.0, f64::INFINITY), &mut rec) {
+                passed += 1;
+            }
+        }
+        let expected = (-1.5f64).exp();
+        assert!(((passed as f64) / (trials as f64) - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn rays_that_miss_are_not_scattered() {
+        let medium = unit_cube(1.5);
+        let r = Ray::new(Point3::new(-1.0, 2.0, 0.5), Vector3::new(1.0, 0.0, 0.0));
+        for trial in 0..100 {
+            start_sample_stream(3, 0, trial);
+            let mut rec = HitRecord::default();
+            assert!(!medium.hit(&r, Interval::new(0.0, f64::INFINITY), &mut rec));
+        }
+    }
+
+    #[test]
+    fn grid_size_must_match_its_data() {
+        let bounds = Aabb::from_points(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        assert!(VoxelGrid::new([2, 2, 2], bounds, vec![1.0; 7]).is_err());
+        assert!(VoxelGrid::new([0, 2, 2], bounds, Vec::new()).is_err());
+    }
+}
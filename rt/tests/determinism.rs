@@ -0,0 +1,51 @@
+use rt::{ render, Framebuffer, RenderSettings, Scene };
+
+// A scene whose filter reaches two pixels into the neighbouring tiles.
+const SCENE: &str = "
+camera:
+  image_width: 40
+  aspect_ratio: 1.25
+  samples_per_pixel: 4
+  max_depth: 6
+  tile_size: 8
+  filter: mitchell
+  tile_order: TILE_ORDER
+  lookfrom: [0.0, 1.0, -4.0]
+materials:
+  - ground: { type: Lambertian, colour: [0.5, 0.5, 0.5] }
+  - shiny: { type: Pbr, colour: [0.8, 0.6, 0.2], metallic: 1.0, roughness: 0.3 }
+objects:
+  - { type: Sphere, centre: [0.0, -100.5, 0.0], radius: 100.0, mat: ground }
+  - { type: Sphere, centre: [0.0, 0.0, 0.0], radius: 0.5, mat: shiny }
+";
+
+fn render_with(tile_order: &str, threads: usize) -> Framebuffer {
+    let scene = Scene::from_yaml_str("determinism.yaml", &SCENE.replace("TILE_ORDER", tile_order))
+        .unwrap();
+    let settings = RenderSettings {
+        seed: Some(7),
+        threads: Some(threads),
+        ..RenderSettings::default()
+    };
+    render(&scene, &settings).unwrap()
+}
+
+#[test]
+fn tile_orders_give_identical_images() {
+    let expected = render_with("spiral", 1);
+    for tile_order in ["scanline", "hilbert"] {
+        assert!(render_with(tile_order, 1).pixels == expected.pixels, "{}", tile_order);
+    }
+}
+
+#[test]
+fn thread_counts_and_tile_orders_give_identical_images() {
+    let expected = render_with("spiral", 1);
+    for tile_order in ["spiral", "scanline", "hilbert"] {
+        for threads in [1, 3, 8] {
+            let framebuffer = render_with(tile_order, threads);
+            assert_eq!((framebuffer.width, framebuffer.height), (expected.width, expected.height));
+            assert!(framebuffer.pixels == expected.pixels, "{} on {} threads", tile_order, threads);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::{ net::TcpListener, thread };
+
+use rt::{ distributed, render, RenderSettings, Scene };
+
+const SCENE: &str = "
+camera:
+  image_width: 64
+  aspect_ratio: 1.5
+  samples_per_pixel: 8
+  max_depth: 8
+  tile_size: 8
+  filter: mitchell
+  lookfrom: [0.0, 1.0, -4.0]
+materials:
+  - ground: { type: Lambertian, colour: [0.5, 0.5, 0.5] }
+  - shiny: { type: Pbr, colour: [0.8, 0.6, 0.2], metallic: 1.0, roughness: 0.3 }
+  - glass: { type: Pbr, colour: [1.0, 1.0, 1.0], roughness: 0.0, transmission: 1.0 }
+objects:
+  - { type: Sphere, centre: [0.0, -100.5, 0.0], radius: 100.0, mat: ground }
+  - { type: Sphere, centre: [-0.6, 0.0, 0.0], radius: 0.5, mat: shiny }
+  - { type: Sphere, centre: [0.6, 0.0, 0.0], radius: 0.5, mat: glass }
+";
+
+fn start_worker() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || distributed::serve_listener(listener));
+    address
+}
+
+#[test]
+fn distributed_render_matches_local_render() {
+    let scene = Scene::from_yaml_str("distributed.yaml", SCENE).unwrap();
+    let settings = RenderSettings { seed: Some(11), threads: Some(2), ..RenderSettings::default() };
+    let local = render(&scene, &settings).unwrap();
+
+    let workers = vec![start_worker(), start_worker()];
+    let distributed = render(&scene, &RenderSettings { workers, ..settings }).unwrap();
+
+    assert_eq!((local.width, local.height), (distributed.width, distributed.height));
+    assert_eq!(local.pixels, distributed.pixels);
+}
+
+#[test]
+fn workers_render_camera_changes_made_after_loading() {
+    let mut scene = Scene::from_yaml_str("distributed.yaml", SCENE).unwrap();
+    scene.camera.vfov = 35.0;
+    scene.camera.max_depth = 3;
+    let settings = RenderSettings { seed: Some(5), threads: Some(1), ..RenderSettings::default() };
+    let local = render(&scene, &settings).unwrap();
+
+    let workers = vec![start_worker()];
+    let distributed = render(&scene, &RenderSettings { workers, ..settings }).unwrap();
+
+    assert_eq!(local.pixels, distributed.pixels);
+}
+
+#[test]
+fn workers_only_render_worlds_read_from_a_file() {
+    let settings = RenderSettings { workers: vec![start_worker()], ..RenderSettings::default() };
+    let mut scene = Scene::from_yaml_str("distributed.yaml", SCENE).unwrap();
+    scene.world.objects.pop();
+    assert!(render(&scene, &settings).is_err());
+
+    let mut scene = Scene::default();
+    scene.camera.image_width = 16;
+    assert!(render(&scene, &settings).is_err());
+}